@@ -0,0 +1,7434 @@
+//! Gate.io WebSocket v4 order latency tester.
+//!
+//! The binary only parses [`Args`] and wires the pieces below together; the
+//! same entry points can be driven from other crates or integration tests:
+//!
+//! - [`init_logging`], [`install_reference_clock`] and [`build_runtime`] set up
+//!   the process the way the CLI does, then [`run`] executes a whole test run
+//!   (orderbook feed, warm trading connections, fire phase, summary).
+//! - [`GateIOAccount`] holds credentials, signing, pending orders and the
+//!   collected [`LatencyStats`]; [`GateIOAccount::place_order`] signs and sends
+//!   an order on any `Sink<Message>` and [`run_with_account`] runs a test with
+//!   an account built by the caller.
+//! - [`ConnectionManager`] is one trading connection; its
+//!   [`handle_frame`](ConnectionManager::handle_frame) matches login and order
+//!   responses, recording latency into the account's stats.
+//! - [`Signer`] / [`build_signer`] with [`sign_string`] and [`rest_sign_string`]
+//!   implement Gate.io's WebSocket and REST signatures.
+//! - [`Error`] classifies failures of Gate.io operations.
+
+#![warn(missing_docs)]
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures_util::stream::{SplitSink, Stream};
+use futures_util::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use log::{debug, error, info};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, watch};
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::TlsError;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, WebSocketConfig};
+use tokio_tungstenite::{client_async_with_config, tungstenite, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+type HmacSha512 = Hmac<Sha512>;
+type HmacSha256 = Hmac<Sha256>;
+type WsStream = WebSocketStream<WsTransport>;
+type WsSink = SplitSink<WsStream, Message>;
+
+/// Bật bởi `--plain`: bỏ emoji và trang trí khỏi output để dễ grep
+static PLAIN_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// Bật bởi `--quiet-until-order`: `say!` được giữ lại (tối đa QUIET_BUFFER_MAX dòng
+/// gần nhất) cho tới lệnh đầu tiên; run lỗi trước đó thì in ra để có ngữ cảnh
+static QUIET_UNTIL_ORDER: AtomicBool = AtomicBool::new(false);
+static QUIET_BUFFER: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+const QUIET_BUFFER_MAX: usize = 200;
+
+// Output chia 2 luồng để có thể `> results.txt 2> debug.log`:
+// `report!` (stdout) cho kết quả latency từng phản hồi và summary,
+// `say!` (stderr) cho mọi trạng thái và chẩn đoán khác. Cả hai tôn trọng `--plain`.
+
+/// Kết quả latency, ra stdout
+macro_rules! report {
+    ($($arg:tt)*) => {
+        println!("{}", plain_text(&format!($($arg)*)))
+    };
+}
+
+/// Trạng thái/chẩn đoán, ra stderr
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if QUIET_UNTIL_ORDER.load(Ordering::Relaxed) {
+            hold_line(plain_text(&format!($($arg)*)).into_owned())
+        } else {
+            eprintln!("{}", plain_text(&format!($($arg)*)))
+        }
+    };
+}
+
+fn hold_line(line: String) {
+    let mut buffer = QUIET_BUFFER.lock().unwrap();
+    if buffer.len() == QUIET_BUFFER_MAX {
+        buffer.pop_front();
+    }
+    buffer.push_back(line);
+}
+
+/// Lệnh đầu tiên được gửi: bỏ phần log đã giữ và bắt đầu in bình thường
+fn end_quiet_phase() {
+    if QUIET_UNTIL_ORDER.swap(false, Ordering::Relaxed) {
+        QUIET_BUFFER.lock().unwrap().clear();
+    }
+}
+
+/// Run thất bại khi vẫn đang im lặng: in lại các dòng đã giữ trước lỗi
+pub fn dump_quiet_buffer() {
+    if QUIET_UNTIL_ORDER.swap(false, Ordering::Relaxed) {
+        for line in QUIET_BUFFER.lock().unwrap().drain(..) {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+fn is_decoration(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // emoji, pictographs
+        | 0x2300..=0x23FF // ⏱ ⏰ ⏳ ⏭
+        | 0x2600..=0x27BF // ⚠ ⚖ ✅ ❌ ✏ ✍
+        | 0x2B00..=0x2BFF
+        | 0xFE0F | 0x200D)
+}
+
+/// Bỏ emoji (và khoảng trắng ngay sau nó) khi `--plain`, giữ nguyên thụt lề và nội dung
+fn plain_text(line: &str) -> std::borrow::Cow<'_, str> {
+    if !PLAIN_OUTPUT.load(Ordering::Relaxed) || !line.chars().any(is_decoration) {
+        return std::borrow::Cow::Borrowed(line);
+    }
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if is_decoration(c) {
+            while chars.peek().is_some_and(|&next| is_decoration(next)) {
+                chars.next();
+            }
+            if chars.peek() == Some(&' ') {
+                chars.next();
+            }
+            continue;
+        }
+        out.push(c);
+    }
+    std::borrow::Cow::Owned(out)
+}
+
+/// Lỗi của các thao tác với Gate.io, phân loại để caller xử lý theo từng loại.
+/// `anyhow` tự chuyển đổi được từ `Error` nên binary vẫn dùng `?` như cũ.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Kết nối/transport WebSocket hoặc REST lỗi, hay connection chưa sẵn sàng
+    #[error("connection error: {0}")]
+    Connect(String),
+    /// Login bị từ chối hoặc thiếu credentials
+    #[error("authentication error: {0}")]
+    Auth(String),
+    /// Lệnh bị chặn ở local (tham số, guard, breaker) hoặc bị server từ chối
+    #[error("order error: {0}")]
+    Order(String),
+    /// Không serialize/parse được JSON
+    #[error("parse error: {0}")]
+    Parse(#[from] serde_json::Error),
+    /// Key không hợp lệ cho thuật toán ký đã chọn
+    #[error("signature error: {0}")]
+    Signature(String),
+    /// Server đóng với code trong --fatal-close-codes: không reconnect
+    #[error("closed by server: {0}")]
+    Closed(String),
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Error::Connect(e.to_string())
+    }
+}
+
+const SYMBOL: &str = "ALCH";
+const SO_COIN_DANH: f64 = 50.0;
+// Cửa sổ trades dùng để tính VWAP gần đây (--with-trades)
+const TRADE_WINDOW: Duration = Duration::from_secs(60);
+const TRADE_WINDOW_MAX: usize = 500;
+// Feed không có message lâu hơn mức này thì việc chuyển sang feed kia được log là failover
+const FEED_STALL: Duration = Duration::from_secs(1);
+// Lý do expire của --order-deadline, tách riêng trong summary
+const DEADLINE_REASON: &str = "no ack before deadline";
+// Số req_id đã finalize được nhớ để nhận ra late response; loop/bench dài không phình bộ nhớ
+const FINALIZED_MEMORY: usize = 4096;
+const KEYRING_SERVICE: &str = "gateio-latency-test";
+const REST_BASE: &str = "https://api.gateio.ws";
+
+/// Gate.io order latency test
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+pub struct Args {
+    /// Seconds to wait after the first valid quote before placing the order
+    #[arg(long, default_value_t = 10, conflicts_with = "trigger_after_ticks")]
+    wait_seconds: u64,
+
+    /// Offer permessage-deflate on every WebSocket and inflate compressed frames
+    /// locally; logs whether the server accepted and the inflate time per message
+    #[arg(long)]
+    compress: bool,
+
+    /// Place the order once N spot.book_ticker updates have been observed
+    /// instead of waiting a fixed time
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    trigger_after_ticks: Option<u64>,
+
+    /// Number of authenticated trading connections that fire orders concurrently
+    #[arg(long, value_name = "K", default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    connections: u64,
+
+    /// Seconds to wait for every trading connection to authenticate; after that
+    /// the fire phase uses the connections that are ready and reports the rest.
+    /// 0 waits for all of them indefinitely
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    warm_timeout: u64,
+
+    /// Comma-separated list of full pairs to test (e.g. ALCH_USDT,ALCH_BTC);
+    /// ALCH/USDT, ALCH-USDT and lowercase are normalized. Defaults to
+    /// ALCH_USDT. With more than one pair a per-pair latency table sorted by
+    /// p50 is printed at shutdown
+    #[arg(long, value_name = "PAIRS", value_delimiter = ',')]
+    pairs: Vec<String>,
+
+    /// Per-pair order amount override, e.g. `--amount BTC_USDT=0.001`;
+    /// repeatable. Takes precedence over --target-notional
+    #[arg(long = "amount", value_name = "PAIR=AMOUNT")]
+    amount_overrides: Vec<AmountOverride>,
+
+    /// Size each order to roughly this quote notional (amount = notional /
+    /// price) so pairs with very different prices are compared fairly
+    #[arg(long, value_name = "QUOTE")]
+    target_notional: Option<f64>,
+
+    /// Simultaneous paired test of two pairs: wait until both books have a price,
+    /// then fire one order on each from the same decision and compare their latencies
+    #[arg(long, value_name = "A,B", value_delimiter = ',', num_args = 1, conflicts_with = "pairs")]
+    compare_symbols: Option<Vec<String>>,
+
+    /// Also place a SELL at best bid alongside each BUY at best ask, from the same
+    /// quote, and compare their latencies
+    #[arg(long)]
+    both_sides: bool,
+
+    /// Alternate BUY at best ask and SELL at best bid on successive orders of
+    /// a connection so the net position stays near zero
+    #[arg(long, conflicts_with_all = ["both_sides", "scenario", "compare_symbols"])]
+    alternate_sides: bool,
+
+    /// Hold every connection's first order at a barrier until all connections are
+    /// connected, then release them at the same instant (logged)
+    #[arg(long)]
+    sync_fire: bool,
+
+    /// `monitor`: stay connected indefinitely logging latency (default);
+    /// `once`: place one order per connection and pair, report and exit;
+    /// `bench`: place --count orders per connection and pair, exit with stats
+    #[arg(long, value_enum, default_value_t = Mode::Monitor)]
+    mode: Mode,
+
+    /// Number of orders each connection places once triggered
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    count: u64,
+
+    /// Read the API secret from this file instead of GATEIO_API_SECRET
+    #[arg(long, value_name = "PATH", conflicts_with = "keyring_user")]
+    secret_file: Option<PathBuf>,
+
+    /// Read the API secret from the OS keyring entry of this user
+    /// (service "gateio-latency-test") instead of GATEIO_API_SECRET
+    #[arg(long, value_name = "USER")]
+    keyring_user: Option<String>,
+
+    /// Send orders even when they are below the pair's minimum notional/amount
+    /// (deliberate edge-case testing)
+    #[arg(long)]
+    no_min_check: bool,
+
+    /// Reject orders priced below this value (disabled by default)
+    #[arg(long, value_name = "PRICE")]
+    min_price: Option<f64>,
+
+    /// Reject orders priced above this value (disabled by default)
+    #[arg(long, value_name = "PRICE")]
+    max_price: Option<f64>,
+
+    /// Place the BUY at this limit price instead of the best ask; the book is
+    /// still required for the staleness/spread/move checks
+    #[arg(long, value_name = "PRICE", conflicts_with = "compare_symbols")]
+    price: Option<f64>,
+
+    /// Place the BUY at PCT percent of the current mid (e.g. 99.5 = 0.5% below
+    /// mid), rounded to the pair's tick size
+    #[arg(long, value_name = "PCT", conflicts_with_all = ["price", "compare_symbols"])]
+    price_pct_of_mid: Option<f64>,
+
+    /// Amend each placed order to this price once its result arrives (spot.order_amend)
+    #[arg(long, value_name = "PRICE")]
+    amend_price: Option<f64>,
+
+    /// Amend each placed order to this amount once its result arrives (spot.order_amend)
+    #[arg(long, value_name = "AMOUNT")]
+    amend_amount: Option<f64>,
+
+    /// Also subscribe to spot.order_book with this many levels per side
+    #[arg(long, value_name = "LEVELS", value_parser = ["5", "10", "20", "50", "100"])]
+    depth_levels: Option<String>,
+
+    /// Only place when (bid_vol - ask_vol) / (bid_vol + ask_vol) over the tracked
+    /// depth exceeds R at decision time and again right before each send
+    /// (-1..1, requires --depth-levels)
+    #[arg(long, value_name = "R", requires = "depth_levels", allow_negative_numbers = true)]
+    min_imbalance: Option<f64>,
+
+    /// Do not place orders while the book is crossed (bid >= ask) or has a zero
+    /// side; such updates are always logged and counted in the summary
+    #[arg(long)]
+    skip_crossed_book: bool,
+
+    /// Skip placement when the best bid/ask spread exceeds this many basis
+    /// points of the mid at decision time (thin book)
+    #[arg(long, value_name = "BPS")]
+    max_spread_bps: Option<f64>,
+
+    /// Gate.io market whose channels and order fields are used
+    #[arg(long, value_enum, default_value_t = Market::Spot)]
+    market: Market,
+
+    /// Account type the orders are booked on: `classic` spot/futures account or a
+    /// `unified` (portfolio margin) account
+    #[arg(long, value_enum, default_value_t = AccountMode::Classic)]
+    account_mode: AccountMode,
+
+    /// Limit order submission to R orders/sec across all connections (token bucket)
+    #[arg(long, value_name = "R", conflicts_with = "ramp")]
+    rate: Option<f64>,
+
+    /// Cap orders in flight (sent, final response not yet received) at N across all
+    /// connections; further sends wait for one to finish
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_inflight: Option<u32>,
+
+    /// Ramp the permitted orders/sec from START to END by STEP every INTERVAL seconds
+    /// (END below START ramps down)
+    #[arg(long, value_name = "START,END,STEP,INTERVAL")]
+    ramp: Option<RampConfig>,
+
+    /// Spacing between consecutive orders of a connection: `fixed:10ms`,
+    /// `uniform:5ms,50ms` or `poisson:RATE` (orders/s); default back-to-back
+    #[arg(long, value_name = "DIST")]
+    inter_order: Option<InterOrder>,
+
+    /// Run the order sequence from a JSONL file instead of `--count` identical
+    /// orders. Each line: {"side","type","amount","price","tif","delay"}; only
+    /// side is required, price defaults to the book ask (BUY) or bid (SELL).
+    /// The whole file is validated at startup
+    #[arg(long, value_name = "PATH", value_parser = Scenario::load, conflicts_with_all = ["count", "inter_order", "compare_symbols"])]
+    scenario: Option<Scenario>,
+
+    /// Ping-pong: place each connection's next order as soon as its previous
+    /// order is finalized (result, rejection or expiry) instead of back-to-back
+    #[arg(long, conflicts_with = "inter_order")]
+    chain: bool,
+
+    /// Pause between a finalized order and the next one in --chain mode
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "chain")]
+    chain_delay: Option<Duration>,
+
+    /// Inject failures to exercise the error paths (testing only, repeatable):
+    /// `drop-every:N` closes a trading connection after every N orders it sends,
+    /// `delay-response:Nms` holds every received frame, `reject-rate:PCT` fails
+    /// that percentage of orders locally as rejections
+    #[arg(long, value_name = "FAULT")]
+    inject: Vec<Injection>,
+
+    /// Acknowledge that --inject runs against the live Gate.io endpoints with
+    /// real orders (there is no mock endpoint override)
+    #[arg(long, requires = "inject")]
+    i_know_this_is_live: bool,
+
+    /// Stop placing further orders this long after the first one, even if
+    /// --count has not been reached
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    duration: Option<Duration>,
+
+    /// Append one row per order response (ack/result) to this CSV file
+    /// (shorthand for `--sink csv:PATH`)
+    #[arg(long, value_name = "PATH")]
+    csv: Option<PathBuf>,
+
+    /// Where per-response latency records go: `stdout`, `csv:PATH`,
+    /// `jsonl:PATH`, `statsd:HOST:PORT` or `none`; repeatable
+    #[arg(long = "sink", value_name = "KIND[:PATH]")]
+    sinks: Vec<SinkSpec>,
+
+    /// Send each response latency as a DogStatsD timing metric over UDP,
+    /// tagged by symbol/account/side (shorthand for `--sink statsd:HOST:PORT`)
+    #[arg(long, value_name = "HOST:PORT")]
+    statsd: Option<String>,
+
+    /// Rotate CSV/JSONL sink files once they reach this size (e.g. 50MB, 512K);
+    /// rotated segments are gzip-compressed to PATH.N.gz
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    rotate_size: Option<u64>,
+
+    /// Rotate CSV/JSONL sink files every SECS seconds
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    rotate_interval: Option<u64>,
+
+    /// Orderbook channel that drives best bid/ask: `book-ticker` (top of book) or
+    /// `order-book-update` (incremental updates, best levels rebuilt locally)
+    #[arg(long, value_enum, default_value_t = BookChannel::BookTicker)]
+    book_channel: BookChannel,
+
+    /// Update interval for `--book-channel order-book-update`
+    #[arg(long, value_name = "INTERVAL", default_value = "100ms", value_parser = ["20ms", "100ms"])]
+    book_interval: String,
+
+    /// Also subscribe to the trades channel and log the recent VWAP at order time
+    #[arg(long)]
+    with_trades: bool,
+
+    /// Private channels to subscribe on each trading connection once authenticated,
+    /// e.g. `orders,balances` (also `usertrades`; market prefix optional)
+    #[arg(long = "subscribe", value_name = "CHANNELS", value_delimiter = ',')]
+    subscribe: Vec<String>,
+
+    /// Open two orderbook connections and keep whichever update is freshest
+    /// (by update id), failing over if one feed stalls or drops
+    #[arg(long)]
+    redundant_feed: bool,
+
+    /// Subscribe the orderbook on trading connection #0 instead of a separate
+    /// socket; book updates and order/auth responses share one authenticated stream
+    #[arg(long, conflicts_with = "redundant_feed")]
+    single_connection: bool,
+
+    /// Decode orderbook frames on N concurrent worker tasks instead of inline on
+    /// the receive loop (order is preserved); logs receive -> price update time
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    decode_workers: Option<u32>,
+
+    /// Log the resolved remote/local address and negotiated TLS version and
+    /// cipher suite of every WebSocket connection (TLS via rustls instead of native-tls)
+    #[arg(long)]
+    log_conn_info: bool,
+
+    /// Set TCP_NODELAY (disable Nagle) on every WebSocket socket; `--tcp-nodelay false` to keep Nagle
+    #[arg(long, value_name = "BOOL", default_value_t = true, action = clap::ArgAction::Set)]
+    tcp_nodelay: bool,
+
+    /// SO_SNDBUF of every WebSocket socket in bytes (default: OS)
+    #[arg(long, value_name = "BYTES")]
+    so_sndbuf: Option<u32>,
+
+    /// SO_RCVBUF of every WebSocket socket in bytes (default: OS)
+    #[arg(long, value_name = "BYTES")]
+    so_rcvbuf: Option<u32>,
+
+    /// Append every book_ticker update (pair, time, ask, bid) as JSONL to this file
+    #[arg(long, value_name = "PATH")]
+    book_log: Option<PathBuf>,
+
+    /// Append every order request sent on the wire (exact JSON, with send time
+    /// and req_id) to this file, for reconciling against exchange records
+    #[arg(long, value_name = "PATH")]
+    audit: Option<PathBuf>,
+
+    /// Print only the first N orderbook updates, then stop printing them
+    /// (updates are still processed and logged to --book-log)
+    #[arg(long, value_name = "N")]
+    book_print_limit: Option<u64>,
+
+    /// Print at most HZ orderbook updates per second across all pairs, on top
+    /// of the price-change heuristic (every update is still processed)
+    #[arg(long, value_name = "HZ")]
+    book_print_hz: Option<f64>,
+
+    /// Add monotonic and wall-clock nanosecond timestamps taken at the
+    /// send/receive points to CSV/JSONL sinks, for correlation with packet captures
+    #[arg(long)]
+    trace_timestamps: bool,
+
+    /// Log the full timestamp chain of the first placed order: decision (book
+    /// snapshot), serialize, send, frame receive, parse, Response 1 and 2
+    #[arg(long)]
+    trace_order: bool,
+
+    /// Also stamp every order send/receive with a reference clock so results
+    /// from different hosts line up: `realtime`, `tai`, `monotonic-raw` or
+    /// `ptp:/dev/ptp0` (PTP hardware clock, Linux). Written to the CSV/JSONL
+    /// sinks as send_ref_ns/recv_ref_ns
+    #[arg(long, value_name = "CLOCK")]
+    reference_clock: Option<ClockSpec>,
+
+    /// `login`: authenticate once via the login channel; `per-message`: skip
+    /// login and sign every order_place/order_amend request individually
+    #[arg(long, value_enum, default_value_t = AuthMode::Login)]
+    auth_mode: AuthMode,
+
+    /// Static prefix for the client order id (`text` field), e.g. `--tag lat1`
+    /// gives `t-lat1-<req_id>`; letters, digits, `_` and `-` only, max 8 chars
+    #[arg(long, value_name = "PREFIX")]
+    tag: Option<String>,
+
+    /// Pause ordering after K consecutive failed orders (circuit breaker)
+    #[arg(long, value_name = "K", value_parser = clap::value_parser!(u32).range(1..))]
+    breaker_threshold: Option<u32>,
+
+    /// Seconds the circuit breaker stays open before a health probe order
+    #[arg(long, value_name = "SECS", default_value_t = 30, requires = "breaker_threshold")]
+    breaker_cooldown: u64,
+
+    /// How long order sends pause after a TOO_MANY_REQUESTS/429 response that
+    /// carries no reset time (header x_gate_ratelimit_reset_timestamp);
+    /// 0 disables backpressure handling
+    #[arg(long, value_name = "DURATION", default_value = "1s", value_parser = parse_backpressure_pause)]
+    backpressure_pause: Duration,
+
+    /// Give up and exit non-zero after N consecutive failed reconnect attempts
+    /// (default: retry forever)
+    #[arg(long, value_name = "N")]
+    max_reconnects: Option<u32>,
+
+    /// WebSocket close codes that end the run instead of reconnecting
+    /// (default: protocol error, unsupported data, policy violation/auth)
+    #[arg(long, value_name = "CODES", value_delimiter = ',', default_value = "1002,1003,1008")]
+    fatal_close_codes: Vec<u16>,
+
+    /// Seed for the shared random generator (backoff jitter...), so a run's
+    /// randomness can be replayed; random and logged if not given
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// On shutdown, stop new orders and wait up to SECS for pending responses
+    #[arg(long, value_name = "SECS", default_value_t = 5)]
+    drain_timeout: u64,
+
+    /// Supervise the orderbook and trading tasks: restart the orderbook feed after
+    /// SECS seconds without a message, and a trading connection that has pending
+    /// orders but no traffic for SECS seconds (or whose task panicked)
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    watchdog: Option<u64>,
+
+    /// Allow each pair to place orders again after the orderbook feed is
+    /// restarted (default: a pair places at most once per run)
+    #[arg(long)]
+    reorder_on_reconnect: bool,
+
+    /// One-shot probe: once every pair's fire phase is over and all its orders
+    /// got their final response (or expired), shut down and print the summary
+    #[arg(long)]
+    exit_after_order: bool,
+
+    /// Expire orders still waiting for their final response after SECS seconds,
+    /// counting them as "no response" in the summary (orders on a dropped
+    /// connection are expired immediately)
+    #[arg(long, value_name = "SECS", default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    order_timeout: u64,
+
+    /// Count an order as timed out when it has no ack at all within this
+    /// long (`500ms`, `2s`; bare number = ms), reported separately from lost
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    order_deadline: Option<Duration>,
+
+    /// Pin the tokio worker threads to these CPU cores, one worker per core
+    /// (e.g. `--cpu-affinity 2,3`)
+    #[arg(long, value_name = "N[,M...]", value_delimiter = ',')]
+    cpu_affinity: Option<Vec<usize>>,
+
+    /// Hold orders requested before authentication completes for up to MS
+    /// and send them once authenticated (default: drop them)
+    #[arg(long, value_name = "MS")]
+    queue_until_auth_ms: Option<u64>,
+
+    /// Re-sign and resend login up to K times on timestamp errors, with the
+    /// clock offset from the server response applied, before giving up
+    #[arg(long, value_name = "K", default_value_t = 3)]
+    auth_retries: u32,
+
+    /// Strip emoji and decoration from printed and logged lines (grep-friendly output)
+    #[arg(long)]
+    plain: bool,
+
+    /// Hold back connection/auth/subscribe/book chatter and start printing at
+    /// the first order send; if the run fails before that, the held lines are
+    /// printed with the error
+    #[arg(long)]
+    quiet_until_order: bool,
+
+    /// API key type; with `ed25519` the secret is an Ed25519 private key
+    /// (PKCS#8 PEM, or a 32-byte seed as hex/base64)
+    #[arg(long, value_enum, default_value_t = KeyType::Hmac)]
+    key_type: KeyType,
+
+    /// Digest of the HMAC signer (`--key-type hmac`); Gate.io requires sha512
+    #[arg(long, value_enum, default_value_t = HmacDigest::Sha512)]
+    hmac_digest: HmacDigest,
+
+    /// Print tradable pairs (optionally only those containing FILTER) with
+    /// their precision and minimum amount, then exit
+    #[arg(long, value_name = "FILTER", num_args = 0..=1, default_missing_value = "")]
+    list_symbols: Option<String>,
+
+    /// Verify the API key with a signed REST request (account balance) before
+    /// connecting, so bad credentials fail fast
+    #[arg(long)]
+    rest_check: bool,
+
+    /// Print the canonical sign string and a signature prefix for login and
+    /// per-message signing (off by default: may expose order params)
+    #[arg(long)]
+    debug_signing: bool,
+
+    /// Skip the order if the latest quote is older than this when the order
+    /// task wakes up (the price is re-read after the wait)
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    max_quote_age_ms: u64,
+
+    /// How order price/amount are rounded to the instrument's tick: `nearest`,
+    /// `up` or `down`. Default is conservative per side: BUY price down, SELL
+    /// price up, amount always down
+    #[arg(long, value_enum)]
+    round_mode: Option<RoundMode>,
+
+    /// Send order price/amount as JSON numbers instead of strings. The text
+    /// form is the same either way (formatted to the instrument's tick)
+    #[arg(long)]
+    json_numbers: bool,
+
+    /// Measure feed latency of every book update (local receive time minus the
+    /// server timestamp `t`, corrected by the clock offset) and print a summary
+    #[arg(long)]
+    measure_book_latency: bool,
+
+    /// Abort the order if the best ask moved more than PCT percent (either way)
+    /// between the trigger and the end of the wait
+    #[arg(long, value_name = "PCT")]
+    abort_if_moved: Option<f64>,
+
+    /// Largest WebSocket message accepted, in bytes (large depth snapshots)
+    #[arg(long, value_name = "BYTES", default_value_t = 64 << 20)]
+    ws_max_message_size: usize,
+
+    /// Largest single WebSocket frame accepted, in bytes
+    #[arg(long, value_name = "BYTES", default_value_t = 16 << 20)]
+    ws_max_frame_size: usize,
+
+    /// Bytes buffered before a write is flushed to the socket
+    #[arg(long, value_name = "BYTES", default_value_t = 128 * 1024)]
+    ws_write_buffer_size: usize,
+
+    /// Decimal places for printed latencies (ms)
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=6))]
+    latency_decimals: u8,
+
+    /// Fail the run (non-zero exit) when the p95 latency of --p95-target
+    /// exceeds MS, for CI
+    #[arg(long, value_name = "MS")]
+    max_p95_ms: Option<f64>,
+
+    /// Which response --max-p95-ms applies to: `receipt` (Response 1, the ack)
+    /// or `result` (Response 2, the order result)
+    #[arg(long, value_enum, default_value_t = LatencyTarget::Receipt)]
+    p95_target: LatencyTarget,
+}
+
+impl Args {
+    /// Số lệnh mỗi connection đặt cho mỗi cặp theo --mode
+    fn order_count(&self) -> u64 {
+        match self.mode {
+            Mode::Once => 1,
+            Mode::Bench | Mode::Monitor => self.count,
+        }
+    }
+
+    /// once/bench tự thoát khi mọi lệnh đã xong, monitor chỉ khi có --exit-after-order
+    fn exits_when_done(&self) -> bool {
+        self.exit_after_order || self.mode != Mode::Monitor
+    }
+
+    /// Các cặp ở dạng chuẩn, bỏ trùng; cặp sai định dạng đã bị `validate` từ chối
+    fn pairs(&self) -> Vec<String> {
+        let requested = self.compare_symbols.as_ref().unwrap_or(&self.pairs);
+        if requested.is_empty() {
+            return vec![format!("{}_USDT", SYMBOL)];
+        }
+        let mut pairs: Vec<String> = Vec::new();
+        for pair in requested.iter().filter_map(|p| canonical_pair(p).ok()) {
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+        pairs
+    }
+
+    /// tungstenite 0.20 không có tùy chỉnh read buffer, chỉ giới hạn message/frame và write buffer
+    fn ws_config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: Some(self.ws_max_message_size),
+            max_frame_size: Some(self.ws_max_frame_size),
+            write_buffer_size: self.ws_write_buffer_size,
+            ..Default::default()
+        }
+    }
+
+    fn dp(&self) -> usize {
+        self.latency_decimals as usize
+    }
+
+    /// Khối lượng lệnh của cặp: --amount, rồi --target-notional theo giá đặt, cuối cùng
+    /// là SO_COIN_DANH. Futures làm tròn xuống số contract nguyên (tối thiểu 1)
+    fn order_amount(&self, pair: &str, price: f64) -> f64 {
+        if let Some(o) = self.amount_overrides.iter().find(|o| o.pair == pair) {
+            return o.amount;
+        }
+        match self.target_notional {
+            Some(notional) if price > 0.0 => match self.market {
+                Market::Spot => notional / price,
+                Market::Futures => (notional / price).floor().max(1.0),
+            },
+            _ => SO_COIN_DANH,
+        }
+    }
+
+    fn log_order_amount(&self, pair: &str, price: f64, amount: f64) {
+        let source = if self.amount_overrides.iter().any(|o| o.pair == pair) {
+            "--amount override".to_string()
+        } else if let Some(notional) = self.target_notional {
+            format!("target notional {} at price {}", notional, price)
+        } else {
+            "default".to_string()
+        };
+        say!("🧮 {} order amount {} ({})", pair, format_decimal(amount), source);
+    }
+
+    fn inject_drop_every(&self) -> Option<u64> {
+        self.inject.iter().find_map(|i| match i {
+            Injection::DropEvery(n) => Some(*n),
+            _ => None,
+        })
+    }
+
+    fn inject_delay_response(&self) -> Option<Duration> {
+        self.inject.iter().find_map(|i| match i {
+            Injection::DelayResponse(d) => Some(*d),
+            _ => None,
+        })
+    }
+
+    fn inject_reject_rate(&self) -> Option<f64> {
+        self.inject.iter().find_map(|i| match i {
+            Injection::RejectRate(pct) => Some(*pct),
+            _ => None,
+        })
+    }
+}
+
+impl Args {
+    /// Kiểm tra các ràng buộc giữa các flag mà clap không diễn tả được; gọi ngay
+    /// sau khi parse, trước khi mở connection nào
+    pub fn validate(&self) -> Result<()> {
+        if self.rate.is_some_and(|rate| rate <= 0.0) {
+            anyhow::bail!("--rate must be greater than 0");
+        }
+        if let (Some(min), Some(max)) = (self.min_price, self.max_price) {
+            if min > max {
+                anyhow::bail!("--min-price ({}) must not be greater than --max-price ({})", min, max);
+            }
+        }
+        if let Some(price) = self.price {
+            if price <= 0.0 {
+                anyhow::bail!("--price must be greater than 0");
+            }
+            if self.min_price.is_some_and(|min| price < min) || self.max_price.is_some_and(|max| price > max) {
+                anyhow::bail!("--price {} is outside the --min-price/--max-price band", price);
+            }
+        }
+        if self.book_print_hz.is_some_and(|hz| !hz.is_finite() || hz <= 0.0) {
+            anyhow::bail!("--book-print-hz must be greater than 0");
+        }
+        if self.target_notional.is_some_and(|n| !n.is_finite() || n <= 0.0) {
+            anyhow::bail!("--target-notional must be greater than 0");
+        }
+        if self.max_p95_ms.is_some_and(|ms| !ms.is_finite() || ms <= 0.0) {
+            anyhow::bail!("--max-p95-ms must be greater than 0");
+        }
+        if self.price_pct_of_mid.is_some_and(|pct| !pct.is_finite() || pct <= 0.0) {
+            anyhow::bail!("--price-pct-of-mid must be greater than 0");
+        }
+        for pair in self.pairs.iter().chain(self.compare_symbols.iter().flatten()) {
+            canonical_pair(pair)?;
+        }
+        if self.compare_symbols.is_some() && self.pairs().len() != 2 {
+            anyhow::bail!("--compare-symbols needs exactly two different pairs, e.g. ALCH_USDT,ALCH_BTC");
+        }
+        if self.mode == Mode::Once && self.count != 1 {
+            anyhow::bail!("--mode once places a single order, --count {} would be ignored; use --mode bench", self.count);
+        }
+        if !self.inject.is_empty() && !self.i_know_this_is_live {
+            anyhow::bail!("--inject hits the live Gate.io endpoints with real orders; add --i-know-this-is-live to confirm");
+        }
+        if self.ws_max_frame_size > self.ws_max_message_size {
+            anyhow::bail!(
+                "--ws-max-frame-size ({}) must not exceed --ws-max-message-size ({})",
+                self.ws_max_frame_size,
+                self.ws_max_message_size
+            );
+        }
+        if self.abort_if_moved.is_some_and(|pct| pct < 0.0) {
+            anyhow::bail!("--abort-if-moved must not be negative");
+        }
+        for channel in &self.subscribe {
+            self.market.private_channel(channel)?;
+        }
+        if self.max_spread_bps.is_some_and(|bps| bps < 0.0) {
+            anyhow::bail!("--max-spread-bps must not be negative");
+        }
+        if let Some(tag) = &self.tag {
+            let valid_chars = tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if tag.is_empty() || tag.len() > 8 || !valid_chars {
+                anyhow::bail!("--tag must be 1-8 characters of [A-Za-z0-9_-], got {:?}", tag);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RampConfig {
+    start: f64,
+    end: f64,
+    step: f64,
+    interval: Duration,
+}
+
+impl FromStr for RampConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<f64> = s
+            .split(',')
+            .map(|p| p.trim().parse::<f64>().map_err(|e| format!("{:?}: {}", p, e)))
+            .collect::<Result<_, _>>()?;
+        let [start, end, step, interval] = parts[..] else {
+            return Err("expected START,END,STEP,INTERVAL".to_string());
+        };
+        if start <= 0.0 || end <= 0.0 || step <= 0.0 || interval <= 0.0 {
+            return Err("all ramp values must be greater than 0".to_string());
+        }
+        Ok(RampConfig { start, end, step, interval: Duration::from_secs_f64(interval) })
+    }
+}
+
+/// Lỗi giả lập của --inject, chỉ bật khi có cờ explicit
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Injection {
+    DropEvery(u64),
+    DelayResponse(Duration),
+    RejectRate(f64),
+}
+
+impl FromStr for Injection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, value) = s.split_once(':').ok_or("expected drop-every:N, delay-response:Nms or reject-rate:PCT")?;
+        match kind.trim() {
+            "drop-every" => match value.trim().parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Injection::DropEvery(n)),
+                _ => Err(format!("drop-every needs a positive order count, got {:?}", value)),
+            },
+            "delay-response" => Ok(Injection::DelayResponse(parse_duration(value)?)),
+            "reject-rate" => match value.trim().trim_end_matches('%').parse::<f64>() {
+                Ok(pct) if (0.0..=100.0).contains(&pct) => Ok(Injection::RejectRate(pct)),
+                _ => Err(format!("reject-rate needs a percentage between 0 and 100, got {:?}", value)),
+            },
+            other => Err(format!("unknown fault {:?} (expected drop-every, delay-response or reject-rate)", other)),
+        }
+    }
+}
+
+/// Khoảng nghỉ giữa các lệnh liên tiếp của một connection (--inter-order)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum InterOrder {
+    Fixed(Duration),
+    Uniform(Duration, Duration),
+    // Số lệnh trung bình mỗi giây, khoảng cách giữa các lệnh theo phân phối mũ
+    Poisson(f64),
+}
+
+impl FromStr for InterOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, value) = s.split_once(':').ok_or("expected fixed:Nms, uniform:Ams,Bms or poisson:RATE")?;
+        match kind.trim() {
+            "fixed" => Ok(InterOrder::Fixed(parse_duration(value)?)),
+            "uniform" => {
+                let (low, high) = value.split_once(',').ok_or("uniform needs two bounds, e.g. uniform:5ms,50ms")?;
+                let (low, high) = (parse_duration(low)?, parse_duration(high)?);
+                if low > high {
+                    return Err(format!("uniform lower bound {:?} is above the upper bound {:?}", low, high));
+                }
+                Ok(InterOrder::Uniform(low, high))
+            }
+            "poisson" => match value.trim().parse::<f64>() {
+                Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(InterOrder::Poisson(rate)),
+                _ => Err(format!("poisson rate {:?} must be a positive number of orders per second", value)),
+            },
+            other => Err(format!("unknown distribution {:?} (expected fixed, uniform or poisson)", other)),
+        }
+    }
+}
+
+impl fmt::Display for InterOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterOrder::Fixed(d) => write!(f, "fixed {:?}", d),
+            InterOrder::Uniform(low, high) => write!(f, "uniform {:?}..{:?}", low, high),
+            InterOrder::Poisson(rate) => write!(f, "poisson {}/s (mean gap {:.1} ms)", rate, 1000.0 / rate),
+        }
+    }
+}
+
+impl InterOrder {
+    fn next_delay(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            InterOrder::Fixed(d) => d,
+            InterOrder::Uniform(low, high) => rng.gen_range(low..=high),
+            InterOrder::Poisson(rate) => {
+                let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                Duration::from_secs_f64(-u.ln() / rate)
+            }
+        }
+    }
+}
+
+/// Một dòng của --scenario; side/type/tif đã được kiểm tra lúc load
+#[derive(Debug, Clone)]
+struct ScenarioStep {
+    side: Side,
+    order_type: String,
+    time_in_force: String,
+    amount: Option<f64>,
+    price: Option<f64>,
+    // Nghỉ trước khi gửi bước này
+    delay: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawScenarioStep {
+    side: String,
+    #[serde(rename = "type")]
+    order_type: Option<String>,
+    amount: Option<f64>,
+    price: Option<f64>,
+    tif: Option<String>,
+    delay: Option<Value>,
+}
+
+#[derive(Debug, Clone)]
+struct Scenario {
+    steps: Vec<ScenarioStep>,
+}
+
+impl Scenario {
+    /// Đọc và validate toàn bộ file, lỗi ở dòng nào thì báo dòng đó
+    fn load(path: &str) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let mut steps = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let step = Self::parse_step(line).map_err(|e| format!("{} line {}: {}", path, index + 1, e))?;
+            steps.push(step);
+        }
+        if steps.is_empty() {
+            return Err(format!("{} contains no scenario steps", path));
+        }
+        Ok(Self { steps })
+    }
+
+    fn parse_step(line: &str) -> Result<ScenarioStep, String> {
+        let raw: RawScenarioStep = serde_json::from_str(line).map_err(|e| e.to_string())?;
+        let side: Side = raw.side.parse().map_err(|e: Error| e.to_string())?;
+        let order_type = raw.order_type.unwrap_or_else(|| "limit".to_string()).to_lowercase();
+        order_type.parse::<OrderType>().map_err(|e| e.to_string())?;
+        let time_in_force = raw.tif.unwrap_or_else(|| "gtc".to_string()).to_lowercase();
+        time_in_force.parse::<TimeInForce>().map_err(|e| e.to_string())?;
+        if raw.amount.is_some_and(|a| !a.is_finite() || a <= 0.0) {
+            return Err(format!("amount must be greater than 0, got {:?}", raw.amount));
+        }
+        if raw.price.is_some_and(|p| !p.is_finite() || p <= 0.0) {
+            return Err(format!("price must be greater than 0, got {:?}", raw.price));
+        }
+        let delay = match raw.delay {
+            None => Duration::ZERO,
+            Some(Value::Number(ms)) if ms.as_f64() == Some(0.0) => Duration::ZERO,
+            Some(Value::Number(ms)) => parse_duration(&ms.to_string())?,
+            Some(Value::String(text)) => parse_duration(&text)?,
+            Some(other) => return Err(format!("delay must be a number of ms or a duration string, got {}", other)),
+        };
+        Ok(ScenarioStep { side, order_type, time_in_force, amount: raw.amount, price: raw.price, delay })
+    }
+}
+
+/// Token bucket giới hạn tốc độ gửi lệnh. Với ramp, rate cho phép tăng dần
+/// theo thời gian tính từ lệnh đầu tiên.
+#[derive(Debug)]
+struct RateLimiter {
+    base_rate: f64,
+    ramp: Option<RampConfig>,
+    bucket: tokio::sync::Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    started: Option<Instant>,
+}
+
+impl RateLimiter {
+    fn new(base_rate: f64, ramp: Option<RampConfig>) -> Self {
+        Self {
+            base_rate,
+            ramp,
+            bucket: tokio::sync::Mutex::new(Bucket { tokens: 1.0, last_refill: Instant::now(), started: None }),
+        }
+    }
+
+    fn rate_at(&self, started: Option<Instant>) -> f64 {
+        match (self.ramp, started) {
+            (Some(ramp), Some(started)) => {
+                let steps = (started.elapsed().as_secs_f64() / ramp.interval.as_secs_f64()).floor();
+                // END < START là ramp giảm dần: STEP luôn dương, áp theo chiều tới END
+                if ramp.end >= ramp.start {
+                    (ramp.start + ramp.step * steps).min(ramp.end)
+                } else {
+                    (ramp.start - ramp.step * steps).max(ramp.end)
+                }
+            }
+            (Some(ramp), None) => ramp.start,
+            (None, _) => self.base_rate,
+        }
+    }
+
+    /// Chờ tới khi có token, trả về rate (orders/sec) đang áp dụng lúc gửi
+    async fn acquire(&self) -> f64 {
+        loop {
+            let mut bucket = self.bucket.lock().await;
+            let now = Instant::now();
+            let started = *bucket.started.get_or_insert(now);
+            let rate = self.rate_at(Some(started));
+            // Burst tối đa 1 lệnh để các lệnh cách đều nhau
+            bucket.tokens = (bucket.tokens + now.duration_since(bucket.last_refill).as_secs_f64() * rate).min(1.0);
+            bucket.last_refill = now;
+            if bucket.tokens >= 1.0 {
+                bucket.tokens -= 1.0;
+                return rate;
+            }
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / rate);
+            drop(bucket);
+            sleep(wait).await;
+        }
+    }
+}
+
+/// Phân loại lỗi đặt lệnh từ status/label của Gate.io
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum GateError {
+    Auth,
+    // Timestamp lệch/hết hạn: ký lại với giờ mới là đủ, khác với sai chữ ký
+    Timestamp,
+    RateLimited,
+    Rejected,
+    Server,
+    Transport,
+}
+
+impl GateError {
+    fn classify(status: &str, label: Option<&str>) -> Self {
+        match label.unwrap_or("") {
+            "INVALID_TIMESTAMP" | "REQUEST_EXPIRED" => return GateError::Timestamp,
+            "INVALID_KEY" | "INVALID_SIGNATURE" | "INVALID_CREDENTIALS" | "FORBIDDEN" | "READ_ONLY" => {
+                return GateError::Auth
+            }
+            "TOO_MANY_REQUESTS" => return GateError::RateLimited,
+            _ => {}
+        }
+        match status {
+            "401" | "403" => GateError::Auth,
+            "429" => GateError::RateLimited,
+            s if s.starts_with('5') => GateError::Server,
+            _ => GateError::Rejected,
+        }
+    }
+
+    /// Login lỗi thường chỉ có message, nên nhận diện timestamp qua cả message
+    fn classify_auth(status: &str, label: Option<&str>, message: &str) -> Self {
+        let message = message.to_lowercase();
+        if message.contains("timestamp") || message.contains("expired") {
+            return GateError::Timestamp;
+        }
+        GateError::classify(status, label)
+    }
+}
+
+fn parse_backpressure_pause(s: &str) -> Result<Duration, String> {
+    if s.trim() == "0" {
+        return Ok(Duration::ZERO);
+    }
+    parse_duration(s)
+}
+
+/// Tạm dừng gửi lệnh khi server báo quá tải: phản hồi RateLimited, hoặc header
+/// `x_gate_ratelimit_requests_remain` về 0 (dừng tới `x_gate_ratelimit_reset_timestamp`)
+#[derive(Debug)]
+struct Backpressure {
+    pause: Duration,
+    until: Mutex<Option<Instant>>,
+}
+
+impl Backpressure {
+    // Không chờ quá lâu nếu reset timestamp lệch đồng hồ hoặc sai
+    const MAX_PAUSE: Duration = Duration::from_secs(60);
+
+    fn new(pause: Duration) -> Self {
+        Self { pause, until: Mutex::new(None) }
+    }
+
+    /// Đọc tín hiệu backpressure từ một phản hồi lệnh; `now_ms` là giờ server hiện tại
+    fn observe(&self, envelope: &Envelope<'_>, now_ms: i64) {
+        if self.pause.is_zero() {
+            return;
+        }
+        let header_number = |key: &str| {
+            let value = envelope.header?.get(key)?;
+            value.as_i64().or_else(|| value.as_str()?.parse().ok())
+        };
+        let reset_in = header_number("x_gate_ratelimit_reset_timestamp")
+            .map(|reset_ms| Duration::from_millis((reset_ms - now_ms).max(0) as u64));
+        let exhausted = header_number("x_gate_ratelimit_requests_remain") == Some(0);
+        let limited = envelope.status.is_some_and(|status| GateError::classify(status, envelope.error_label) == GateError::RateLimited);
+        if exhausted || limited {
+            let pause = reset_in.filter(|d| !d.is_zero()).unwrap_or(self.pause).min(Self::MAX_PAUSE);
+            self.apply(pause, if limited { "rate limited by server" } else { "request quota exhausted" });
+        }
+    }
+
+    fn apply(&self, pause: Duration, reason: &str) {
+        let until = Instant::now() + pause;
+        let mut current = self.until.lock().unwrap();
+        if current.is_some_and(|current| current >= until) {
+            return;
+        }
+        say!("🐢 Backpressure applied ({}): pausing order sends for {} ms", reason, pause.as_millis());
+        *current = Some(until);
+    }
+
+    /// Chờ tới khi hết thời gian tạm dừng (nếu có) trước khi gửi lệnh
+    async fn wait(&self) {
+        loop {
+            let Some(until) = *self.until.lock().unwrap() else {
+                return;
+            };
+            let now = Instant::now();
+            if now >= until {
+                let mut current = self.until.lock().unwrap();
+                if *current == Some(until) {
+                    *current = None;
+                    say!("🐇 Backpressure lifted, resuming order sends");
+                }
+                continue;
+            }
+            sleep(until - now).await;
+        }
+    }
+}
+
+/// Ngắt đặt lệnh sau `threshold` lỗi liên tiếp trong `cooldown`; hết cooldown thì
+/// lệnh kế tiếp là probe, thành công mới đóng lại
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self { threshold, cooldown, state: Mutex::new(BreakerState::default()) }
+    }
+
+    fn allow(&self) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let Some(open_until) = state.open_until else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if now < open_until {
+            return Err(Error::Order(format!(
+                "circuit breaker open for another {:.1}s",
+                open_until.duration_since(now).as_secs_f64()
+            )));
+        }
+        if state.probing {
+            return Err(Error::Order("circuit breaker half-open, waiting for health probe".to_string()));
+        }
+        state.probing = true;
+        say!("🩺 Circuit breaker cooldown over, sending health probe order");
+        Ok(())
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_until.is_some() {
+            say!("✅ Circuit breaker closed: health probe succeeded, resuming orders");
+        }
+        *state = BreakerState::default();
+    }
+
+    fn record_failure(&self, kind: GateError) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        if state.probing || state.consecutive_failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            state.probing = false;
+            say!(
+                "🧯 Circuit breaker tripped after {} consecutive failure(s) (last: {:?}), pausing orders for {}s",
+                state.consecutive_failures,
+                kind,
+                self.cooldown.as_secs()
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SharePrice {
+    gia_mua_gate: Option<f64>,
+    // Best bid từ book_ticker ("b")
+    gia_ban_gate: Option<f64>,
+    time_gia_gate: Option<String>,
+    // Thời điểm nhận update gần nhất, để kiểm tra quote cũ
+    updated_at: Option<Instant>,
+    // Timestamp của server ("t", ms) trong book_ticker, để đo độ trễ của feed
+    server_time_ms: Option<u64>,
+    orderbook_ready: bool,
+    // Top levels (price, amount) từ spot.order_book khi bật --depth-levels
+    bids: Vec<(f64, f64)>,
+    asks: Vec<(f64, f64)>,
+    // Trades gần đây (thời điểm nhận, price, amount) từ spot.trades khi bật --with-trades
+    trades: VecDeque<(Instant, f64, f64)>,
+    // Book dựng từ các update của order_book_update khi --book-channel order-book-update
+    local_book: LocalBook,
+    // --redundant-feed: update id ("u") và feed của update được nhận gần nhất
+    last_update_id: Option<u64>,
+    source: Option<usize>,
+    // Trade id gần nhất, để không đếm trùng trade nhận từ cả 2 feed
+    last_trade_id: Option<u64>,
+}
+
+/// Book cục bộ từ update incremental. Key là bit pattern của price: với số dương
+/// thứ tự của bit trùng thứ tự số nên BTreeMap giữ được level tốt nhất ở đầu/cuối.
+/// Không lấy snapshot REST nên chỉ chính xác khi các level tốt nhất đã có update.
+#[derive(Debug, Clone, Default)]
+struct LocalBook {
+    bids: BTreeMap<u64, f64>,
+    asks: BTreeMap<u64, f64>,
+}
+
+impl LocalBook {
+    fn apply(&mut self, result: &Value) {
+        if result.get("full").and_then(|f| f.as_bool()) == Some(true) {
+            self.bids.clear();
+            self.asks.clear();
+        }
+        for (side, levels) in [(&mut self.bids, result.get("b")), (&mut self.asks, result.get("a"))] {
+            for (price, amount) in parse_levels(levels) {
+                if amount == 0.0 {
+                    side.remove(&price.to_bits());
+                } else {
+                    side.insert(price.to_bits(), amount);
+                }
+            }
+        }
+    }
+
+    fn best_bid(&self) -> Option<f64> {
+        self.bids.keys().next_back().map(|bits| f64::from_bits(*bits))
+    }
+
+    fn best_ask(&self) -> Option<f64> {
+        self.asks.keys().next().map(|bits| f64::from_bits(*bits))
+    }
+}
+
+/// Một dòng JSONL của --book-log
+#[derive(Debug, Serialize)]
+struct BookSnapshot<'a> {
+    pair: &'a str,
+    time: &'a str,
+    ask: Option<f64>,
+    bid: Option<f64>,
+    server_time_ms: Option<u64>,
+}
+
+impl SharePrice {
+    /// Imbalance khối lượng bid/ask trên các level đang theo dõi, trong khoảng -1..1
+    fn imbalance(&self) -> Option<f64> {
+        if self.bids.is_empty() || self.asks.is_empty() {
+            return None;
+        }
+        let bid_vol: f64 = self.bids.iter().map(|(_, amount)| amount).sum();
+        let ask_vol: f64 = self.asks.iter().map(|(_, amount)| amount).sum();
+        let total = bid_vol + ask_vol;
+        (total > 0.0).then(|| (bid_vol - ask_vol) / total)
+    }
+
+    fn mid(ask: f64, bid: f64) -> Option<f64> {
+        (ask > 0.0 && bid > 0.0).then(|| (ask + bid) / 2.0)
+    }
+
+    /// Spread ask - bid tính theo basis point của mid
+    fn spread_bps(ask: f64, bid: f64) -> Option<f64> {
+        let mid = (ask + bid) / 2.0;
+        (ask > 0.0 && bid > 0.0 && mid > 0.0).then(|| (ask - bid) / mid * 10_000.0)
+    }
+
+    /// Mid và spread (giá tuyệt đối, bps) của best ask/bid đang lưu; None khi thiếu một phía
+    fn quote_mid(&self) -> Option<f64> {
+        Self::mid(self.gia_mua_gate?, self.gia_ban_gate?)
+    }
+
+    fn quote_spread(&self) -> Option<(f64, f64)> {
+        let (ask, bid) = (self.gia_mua_gate?, self.gia_ban_gate?);
+        Some((ask - bid, Self::spread_bps(ask, bid)?))
+    }
+
+    fn push_trade(&mut self, price: f64, amount: f64) {
+        let now = Instant::now();
+        self.trades.push_back((now, price, amount));
+        while self.trades.len() > TRADE_WINDOW_MAX
+            || self.trades.front().is_some_and(|(at, _, _)| now.duration_since(*at) > TRADE_WINDOW)
+        {
+            self.trades.pop_front();
+        }
+    }
+
+    /// VWAP và số trades trong cửa sổ gần đây
+    fn vwap(&self) -> Option<(f64, usize)> {
+        let now = Instant::now();
+        let recent = self.trades.iter().filter(|(at, _, _)| now.duration_since(*at) <= TRADE_WINDOW);
+        let (notional, volume, n) = recent.fold((0.0, 0.0, 0), |(notional, volume, n), (_, price, amount)| {
+            (notional + price * amount, volume + amount, n + 1)
+        });
+        (volume > 0.0).then(|| (notional / volume, n))
+    }
+}
+
+/// Dạng chuẩn của cặp là `BASE_QUOTE` viết hoa, dùng chung cho subscribe, so khớp
+/// update và đặt lệnh. Nhận cả `ALCH/USDT`, `ALCH-USDT`, `alch_usdt`; cặp có tồn tại
+/// hay không do validate_instruments kiểm tra với danh sách instrument
+fn canonical_pair(input: &str) -> Result<String, Error> {
+    let parts: Vec<&str> = input.trim().split(['/', '-', '_']).collect();
+    let valid = matches!(parts.as_slice(), [base, quote] if [base, quote]
+        .iter()
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric())));
+    if !valid {
+        return Err(Error::Order(format!(
+            "Invalid currency pair {:?} (expected BASE_QUOTE, BASE/QUOTE or BASE-QUOTE, e.g. ALCH_USDT)",
+            input
+        )));
+    }
+    Ok(parts.join("_").to_uppercase())
+}
+
+/// Khối lượng cố định cho một cặp (--amount PAIR=AMOUNT)
+#[derive(Debug, Clone, PartialEq)]
+struct AmountOverride {
+    pair: String,
+    amount: f64,
+}
+
+impl FromStr for AmountOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (pair, amount) = s.split_once('=').ok_or("expected PAIR=AMOUNT, e.g. BTC_USDT=0.001")?;
+        let pair = canonical_pair(pair).map_err(|e| e.to_string())?;
+        match amount.trim().parse::<f64>() {
+            Ok(amount) if amount.is_finite() && amount > 0.0 => Ok(Self { pair, amount }),
+            _ => Err(format!("amount for {} must be a positive number, got {:?}", pair, amount)),
+        }
+    }
+}
+
+/// Kiểm tra cặp có tồn tại qua REST trước khi subscribe, để symbol sai báo lỗi ngay
+/// thay vì im lặng không có update. Không gọi được REST thì chỉ cảnh báo.
+/// Trả về tick giá/khối lượng của những cặp đọc được từ REST
+async fn validate_instruments(market: Market, pairs: &[String]) -> Result<HashMap<String, Precision>> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let mut precisions = HashMap::new();
+    for pair in pairs {
+        match client.get(market.instrument_url(pair)).send().await {
+            Ok(response) if response.status().is_success() => {
+                say!("✅ {} is a listed {:?} instrument", pair, market);
+                match response.json::<Value>().await {
+                    Ok(info) => {
+                        precisions.insert(pair.clone(), Precision::from_instrument(market, &info));
+                    }
+                    Err(e) => say!("⚠️ Could not read {} precision, prices are sent unrounded: {}", pair, e),
+                }
+            }
+            Ok(response) if response.status().is_client_error() => {
+                anyhow::bail!("Unknown {:?} instrument {} (HTTP {})", market, pair, response.status());
+            }
+            Ok(response) => say!("⚠️ Could not validate {}: HTTP {}", pair, response.status()),
+            Err(e) => say!("⚠️ Could not validate {}: {}", pair, e),
+        }
+    }
+    Ok(precisions)
+}
+
+/// --list-symbols: in các cặp đang giao dịch được, lọc theo chuỗi con (không phân biệt hoa thường)
+async fn list_symbols(market: Market, filter: &str) -> Result<()> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(30)).build()?;
+    let response = client.get(market.instruments_url()).send().await?.error_for_status()?;
+    let instruments: Vec<Value> = response.json().await?;
+    let filter = filter.to_uppercase();
+    let tick = |step: Option<Step>| step.map_or("-".to_string(), |s| format!("{:.*}", s.decimals, s.size));
+
+    let mut rows: Vec<(String, String, String, String)> = instruments
+        .iter()
+        .filter(|info| match market {
+            Market::Spot => info.get("trade_status").and_then(|s| s.as_str()) == Some("tradable"),
+            Market::Futures => !info.get("in_delisting").and_then(|d| d.as_bool()).unwrap_or(false),
+        })
+        .filter_map(|info| {
+            let name = info.get("id").or_else(|| info.get("name")).and_then(|n| n.as_str())?;
+            if !name.contains(&filter) {
+                return None;
+            }
+            let precision = Precision::from_instrument(market, info);
+            let min_amount = match market {
+                Market::Spot => info.get("min_base_amount").and_then(|m| m.as_str()).map(str::to_string),
+                Market::Futures => info.get("order_size_min").map(|m| m.to_string()),
+            };
+            Some((name.to_string(), tick(precision.price), tick(precision.amount), min_amount.unwrap_or_else(|| "-".to_string())))
+        })
+        .collect();
+    rows.sort();
+
+    report!("{:<20} {:>14} {:>14} {:>14}", "PAIR", "PRICE TICK", "AMOUNT TICK", "MIN AMOUNT");
+    for (name, price, amount, min_amount) in &rows {
+        report!("{:<20} {:>14} {:>14} {:>14}", name, price, amount, min_amount);
+    }
+    say!("📋 {} tradable {:?} pair(s){}", rows.len(), market, if filter.is_empty() { String::new() } else { format!(" matching {:?}", filter) });
+    Ok(())
+}
+
+/// Hai phản hồi của một lệnh: Response 1 là receipt (ack `"ack": true`, server đã
+/// nhận request, gần với độ trễ mạng), Response 2 là result (lệnh đã qua matching
+/// engine, gồm cả thời gian xử lý của server)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum LatencyTarget {
+    Receipt,
+    Result,
+}
+
+impl LatencyTarget {
+    fn response_num(self) -> u32 {
+        match self {
+            LatencyTarget::Receipt => 1,
+            LatencyTarget::Result => 2,
+        }
+    }
+}
+
+fn response_label(response_num: u32) -> &'static str {
+    match response_num {
+        1 => "receipt",
+        _ => "result",
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum RoundMode {
+    Nearest,
+    Up,
+    Down,
+}
+
+/// Bước giá hoặc khối lượng, ví dụ 0.00001 (5 chữ số thập phân)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step {
+    size: f64,
+    decimals: usize,
+}
+
+impl Step {
+    fn from_decimals(decimals: u64) -> Self {
+        let decimals = decimals.min(18) as usize;
+        Self { size: 10f64.powi(-(decimals as i32)), decimals }
+    }
+
+    /// Tick dạng chuỗi như "0.05" của futures
+    fn parse(tick: &str) -> Option<Self> {
+        let size: f64 = tick.parse().ok().filter(|size: &f64| *size > 0.0)?;
+        let decimals = tick.split_once('.').map_or(0, |(_, frac)| frac.trim_end_matches('0').len());
+        Some(Self { size, decimals })
+    }
+
+    /// Làm tròn theo bội số của tick; sai số float (0.02789 / 0.00001 = 2788.9999...)
+    /// được coi là đã nằm đúng tick trước khi floor/ceil
+    fn round(self, value: f64, mode: RoundMode) -> f64 {
+        let ticks = value / self.size;
+        let nearest = ticks.round();
+        let ticks = if (ticks - nearest).abs() < 1e-9 * nearest.abs().max(1.0) {
+            nearest
+        } else {
+            match mode {
+                RoundMode::Nearest => nearest,
+                RoundMode::Up => ticks.ceil(),
+                RoundMode::Down => ticks.floor(),
+            }
+        };
+        ticks * self.size
+    }
+
+    fn format(self, value: f64, mode: RoundMode) -> String {
+        format!("{:.*}", self.decimals, self.round(value, mode))
+    }
+}
+
+/// Số thập phân dạng chuỗi ổn định khi chưa biết tick: tối đa 10 chữ số lẻ, bỏ số 0
+/// thừa, nên 50.0 luôn là "50" và 0.1 + 0.2 là "0.3"
+fn format_decimal(value: f64) -> String {
+    let text = format!("{:.10}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    match text {
+        "" | "-" | "-0" => "0".to_string(),
+        _ => text.to_string(),
+    }
+}
+
+/// Giá/khối lượng của lệnh: `text` đã được format cố định, `numeric` quyết định gửi
+/// dạng chuỗi JSON (mặc định) hay số JSON (--json-numbers)
+#[derive(Debug, Clone, PartialEq)]
+struct OrderNumber {
+    text: String,
+    numeric: bool,
+}
+
+impl OrderNumber {
+    fn new(text: String, numeric: bool) -> Self {
+        Self { text, numeric }
+    }
+}
+
+impl std::fmt::Display for OrderNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Serialize for OrderNumber {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.numeric {
+            // Ghi nguyên văn chuỗi đã format: đi qua serde_json::Number thì giá nhỏ thành
+            // dạng mũ (1.23e-6) và mất chữ số sau ~17 chữ số có nghĩa
+            self.text.parse::<serde_json::Number>().map_err(serde::ser::Error::custom)?;
+            let raw = serde_json::value::RawValue::from_string(self.text.clone()).map_err(serde::ser::Error::custom)?;
+            raw.serialize(serializer)
+        } else {
+            serializer.serialize_str(&self.text)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OrderNumber {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::String(text) => Ok(Self::new(text, false)),
+            Value::Number(number) => Ok(Self::new(number.to_string(), true)),
+            other => Err(serde::de::Error::custom(format!("expected a decimal string or number, got {}", other))),
+        }
+    }
+}
+
+/// Tick và mức tối thiểu của instrument lấy từ REST; thiếu field thì giá trị được
+/// gửi nguyên như cũ
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Precision {
+    price: Option<Step>,
+    amount: Option<Step>,
+    // Spot: giá trị quote tối thiểu (amount * price) và khối lượng base tối thiểu
+    min_quote: Option<f64>,
+    min_base: Option<f64>,
+}
+
+impl Precision {
+    fn from_instrument(market: Market, info: &Value) -> Self {
+        let number = |key: &str| info.get(key).and_then(|v| v.as_str()).and_then(|v| v.parse::<f64>().ok()).filter(|v| *v > 0.0);
+        match market {
+            Market::Spot => Self {
+                price: info.get("precision").and_then(|p| p.as_u64()).map(Step::from_decimals),
+                amount: info.get("amount_precision").and_then(|p| p.as_u64()).map(Step::from_decimals),
+                min_quote: number("min_quote_amount"),
+                min_base: number("min_base_amount"),
+            },
+            // Size futures là số nguyên contract, đã được kiểm tra trong create_order
+            Market::Futures => Self {
+                price: info.get("order_price_round").and_then(|p| p.as_str()).and_then(Step::parse),
+                ..Self::default()
+            },
+        }
+    }
+
+    /// Lệnh BUY bị giới hạn theo giá trị quote, SELL theo cả khối lượng base lẫn
+    /// giá trị quote; trả về mô tả phần thiếu nếu lệnh quá nhỏ
+    fn check_minimums(&self, side: Side, price: f64, quantity: f64) -> Option<String> {
+        let notional = price * quantity;
+        if let Some(min) = self.min_quote.filter(|min| notional < *min) {
+            return Some(format!("{} notional {} is below the minimum {} (short by {})", side, notional, min, min - notional));
+        }
+        if side == Side::Sell {
+            if let Some(min) = self.min_base.filter(|min| quantity < *min) {
+                return Some(format!("SELL amount {} is below the minimum {} (short by {})", quantity, min, min - quantity));
+            }
+        }
+        None
+    }
+}
+
+/// Kết quả của phản hồi api: Gate.io v4 lồng trong `data.result`, một số phản hồi
+/// để ở `result` top-level; ưu tiên `data.result` khi có cả hai
+fn response_result(response: &Value) -> Option<&Value> {
+    response
+        .get("data")
+        .and_then(|d| d.get("result"))
+        .filter(|r| !r.is_null())
+        .or_else(|| response.get("result").filter(|r| !r.is_null()))
+}
+
+/// Các field chung của một phản hồi WS. v4 lồng channel/event/status trong `header`,
+/// bản cũ và một số channel để ở top-level; mọi branch của handle_message đọc từ đây
+#[derive(Debug, Clone, Copy)]
+struct Envelope<'a> {
+    header: Option<&'a serde_json::Map<String, Value>>,
+    channel: &'a str,
+    event: &'a str,
+    status: Option<&'a str>,
+    request_id: Option<&'a str>,
+    ack: bool,
+    result: Option<&'a Value>,
+    error_label: Option<&'a str>,
+    error_message: Option<&'a str>,
+}
+
+fn parse_envelope(response: &Value) -> Envelope<'_> {
+    let header = response.get("header").and_then(|h| h.as_object());
+    let field = |key: &str| header.and_then(|h| h.get(key)).or_else(|| response.get(key)).and_then(|v| v.as_str());
+    // Lỗi v4 ở `data.errs`, bản cũ ở `error` (object hoặc chuỗi)
+    let errs = response.get("data").and_then(|d| d.get("errs")).or_else(|| response.get("error").filter(|e| e.is_object()));
+    let result = response_result(response);
+    Envelope {
+        header,
+        channel: field("channel").unwrap_or(""),
+        event: field("event").unwrap_or(""),
+        status: field("status"),
+        request_id: field("request_id").filter(|id| !id.is_empty()),
+        ack: response.get("ack").and_then(|a| a.as_bool()).unwrap_or(false),
+        result,
+        error_label: errs.and_then(|e| e.get("label")).and_then(|l| l.as_str()),
+        error_message: header
+            .and_then(|h| h.get("message"))
+            .or_else(|| errs.and_then(|e| e.get("message")))
+            .or_else(|| response.get("error").filter(|e| e.is_string()))
+            .or_else(|| result.and_then(|r| r.get("message")))
+            .and_then(|m| m.as_str()),
+    }
+}
+
+/// Cắt chuỗi tối đa `max_chars` ký tự, không bao giờ cắt giữa một ký tự UTF-8
+/// (message lỗi của Gate.io có thể chứa ký tự non-ASCII)
+fn truncate_str(s: &str, max_chars: usize) -> &str {
+    match s.char_indices().nth(max_chars) {
+        Some((idx, _)) => &s[..idx],
+        None => s,
+    }
+}
+
+fn parse_levels(levels: Option<&Value>) -> Vec<(f64, f64)> {
+    levels
+        .and_then(|l| l.as_array())
+        .map(|levels| {
+            levels
+                .iter()
+                .filter_map(|level| {
+                    // Spot: ["price", "amount"], futures: {"p": "price", "s": size}
+                    if let Some(p) = level.get("p") {
+                        let price = p.as_str()?.parse().ok()?;
+                        let amount = level.get("s")?.as_f64()?;
+                        return Some((price, amount));
+                    }
+                    let price = level.get(0)?.as_str()?.parse().ok()?;
+                    let amount = level.get(1)?.as_str()?.parse().ok()?;
+                    Some((price, amount))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Thời gian xử lý phía Gate.io (ms) từ `x_in_time`/`x_out_time` (microseconds) trong header
+fn server_processing_ms(header: Option<&serde_json::Map<String, Value>>) -> Option<f64> {
+    let micros = |key: &str| {
+        let value = header?.get(key)?;
+        value.as_u64().or_else(|| value.as_str()?.parse().ok())
+    };
+    let (x_in, x_out) = (micros("x_in_time")?, micros("x_out_time")?);
+    Some(x_out.checked_sub(x_in)? as f64 / 1000.0)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrderbookSubscribe {
+    time: u64,
+    channel: String,
+    event: String,
+    payload: Vec<String>,
+}
+
+/// Subscribe channel private: chữ ký trên `channel=..&event=..&time=..`
+#[derive(Debug, Serialize)]
+struct PrivateSubscribe {
+    time: u64,
+    channel: String,
+    event: String,
+    payload: Vec<String>,
+    auth: ChannelAuth,
+}
+
+#[derive(Debug, Serialize)]
+struct ChannelAuth {
+    method: &'static str,
+    #[serde(rename = "KEY")]
+    key: String,
+    #[serde(rename = "SIGN")]
+    sign: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthRequest {
+    time: u64,
+    channel: String,
+    event: String,
+    payload: AuthPayload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthPayload {
+    api_key: String,
+    signature: String,
+    timestamp: String,
+    req_id: String,
+}
+
+/// Channel dùng để theo dõi giá tốt nhất
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum BookChannel {
+    BookTicker,
+    OrderBookUpdate,
+}
+
+impl BookChannel {
+    fn name(self) -> &'static str {
+        match self {
+            BookChannel::BookTicker => "book_ticker",
+            BookChannel::OrderBookUpdate => "order_book_update",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AuthMode {
+    Login,
+    PerMessage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Mode {
+    Once,
+    Bench,
+    Monitor,
+}
+
+/// Loại API key: HMAC secret (mặc định) hoặc private key ed25519
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyType {
+    /// HMAC API secret
+    Hmac,
+    /// Ed25519 private key (PEM, or 32/64-byte hex/base64 seed)
+    Ed25519,
+}
+
+/// Private key ed25519 dạng PEM (PKCS#8), hoặc seed 32 byte dạng hex/base64
+fn parse_ed25519_key(secret: &str) -> Result<ed25519_dalek::SigningKey, Error> {
+    use base64::Engine;
+    use ed25519_dalek::pkcs8::DecodePrivateKey;
+
+    let secret = secret.trim();
+    if secret.starts_with("-----BEGIN") {
+        return ed25519_dalek::SigningKey::from_pkcs8_pem(secret).map_err(|e| Error::Signature(e.to_string()));
+    }
+    let bytes = hex::decode(secret)
+        .or_else(|_| base64::engine::general_purpose::STANDARD.decode(secret))
+        .map_err(|_| Error::Signature("ed25519 key is neither PEM, hex nor base64".to_string()))?;
+    // 64 byte = seed + public key, chỉ cần 32 byte đầu
+    let seed: [u8; 32] = bytes
+        .get(..32)
+        .filter(|_| bytes.len() == 32 || bytes.len() == 64)
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or_else(|| Error::Signature(format!("ed25519 key must be 32 or 64 bytes, got {}", bytes.len())))?;
+    Ok(ed25519_dalek::SigningKey::from_bytes(&seed))
+}
+
+/// Chuỗi canonical mà Gate.io yêu cầu ký, dùng chung cho mọi thuật toán
+pub fn sign_string(channel: &str, request_param: &str, ts: u64) -> String {
+    format!("api\n{}\n{}\n{}", channel, request_param, ts)
+}
+
+/// Chuỗi ký REST v4: `METHOD\n/api/v4/path\nquery\nhex(SHA512(body))\ntimestamp`,
+/// query không có dấu `?`, body rỗng vẫn được hash
+pub fn rest_sign_string(method: &str, path: &str, query: &str, body: &str, ts: u64) -> String {
+    let body_hash = hex::encode(Sha512::digest(body.as_bytes()));
+    format!("{}\n{}\n{}\n{}\n{}", method.to_uppercase(), path, query, body_hash, ts)
+}
+
+/// Thuật toán ký request; thêm scheme mới (REST, đổi version protocol...)
+/// chỉ cần implement trait này
+pub trait Signer: Send + Sync + fmt::Debug {
+    /// Tên thuật toán cho log, ví dụ `hmac-sha512`
+    fn name(&self) -> &'static str;
+
+    /// Ký nguyên chuỗi `message` (chữ ký subscribe channel private dùng format khác)
+    fn sign_message(&self, message: &str) -> String;
+
+    /// Chữ ký WS request: ký [`sign_string`] của channel, payload và timestamp
+    fn sign(&self, channel: &str, request_param: &str, ts: u64) -> String {
+        self.sign_message(&sign_string(channel, request_param, ts))
+    }
+
+    /// Chữ ký REST request: ký [`rest_sign_string`]
+    fn sign_rest(&self, method: &str, path: &str, query: &str, body: &str, ts: u64) -> String {
+        self.sign_message(&rest_sign_string(method, path, query, body, ts))
+    }
+}
+
+/// Header xác thực của REST request (`KEY`, `SIGN`, `Timestamp`)
+#[derive(Debug, Clone)]
+struct RestAuth {
+    key: String,
+    sign: String,
+    timestamp: String,
+}
+
+impl RestAuth {
+    fn apply(self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        request.header("KEY", self.key).header("SIGN", self.sign).header("Timestamp", self.timestamp)
+    }
+}
+
+/// HMAC với API secret, chữ ký encode hex. Gate.io dùng SHA512 (mặc định),
+/// SHA256 cho endpoint/sàn khác
+#[derive(Clone)]
+struct HmacSigner<M> {
+    // Key đã nạp sẵn, mỗi lần ký chỉ clone state
+    mac: M,
+    name: &'static str,
+}
+
+type HmacSha512Signer = HmacSigner<HmacSha512>;
+type HmacSha256Signer = HmacSigner<HmacSha256>;
+
+impl<M: Mac + hmac::digest::KeyInit> HmacSigner<M> {
+    fn new(secret: &str, name: &'static str) -> Result<Self, Error> {
+        let mac = <M as Mac>::new_from_slice(secret.as_bytes()).map_err(|e| Error::Signature(e.to_string()))?;
+        Ok(Self { mac, name })
+    }
+}
+
+impl<M> fmt::Debug for HmacSigner<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacSigner").field("name", &self.name).finish_non_exhaustive()
+    }
+}
+
+impl<M: Mac + Clone + Send + Sync> Signer for HmacSigner<M> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn sign_message(&self, message: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(message.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+}
+
+/// Ed25519 ký cùng chuỗi canonical, chữ ký encode base64 theo spec của Gate.io
+#[derive(Debug)]
+struct Ed25519Signer {
+    key: ed25519_dalek::SigningKey,
+}
+
+impl Signer for Ed25519Signer {
+    fn name(&self) -> &'static str {
+        "ed25519"
+    }
+
+    fn sign_message(&self, message: &str) -> String {
+        use base64::Engine;
+        let signature = ed25519_dalek::Signer::sign(&self.key, message.as_bytes());
+        base64::engine::general_purpose::STANDARD.encode(signature.to_bytes())
+    }
+}
+
+/// Hàm hash của HMAC khi `--key-type hmac`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HmacDigest {
+    /// HMAC-SHA512, Gate.io's scheme
+    Sha512,
+    /// HMAC-SHA256, for other endpoints/exchanges
+    Sha256,
+}
+
+/// Tạo [`Signer`] theo loại key và digest; key được parse một lần ở đây nên key
+/// sai format báo lỗi ngay lúc khởi tạo thay vì ở lệnh đầu tiên
+pub fn build_signer(key_type: KeyType, digest: HmacDigest, secret: &str) -> Result<Box<dyn Signer>, Error> {
+    Ok(match key_type {
+        KeyType::Hmac => match digest {
+            HmacDigest::Sha512 => Box::new(HmacSha512Signer::new(secret, "hmac-sha512")?),
+            HmacDigest::Sha256 => Box::new(HmacSha256Signer::new(secret, "hmac-sha256")?),
+        },
+        KeyType::Ed25519 => Box::new(Ed25519Signer { key: parse_ed25519_key(secret)? }),
+    })
+}
+
+/// Chữ ký đi kèm từng request khi `--auth-mode per-message`
+#[derive(Debug, Serialize, Deserialize)]
+struct RequestAuth {
+    api_key: String,
+    signature: String,
+    timestamp: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrderRequest {
+    time: u64,
+    channel: String,
+    event: String,
+    payload: OrderPayload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrderPayload {
+    req_id: String,
+    req_param: OrderParam,
+    #[serde(flatten)]
+    auth: Option<RequestAuth>,
+}
+
+/// Spot và futures dùng tên channel và field khác nhau cho cùng một thao tác
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Market {
+    Spot,
+    /// USDT-settled perpetual futures
+    Futures,
+}
+
+/// Unified account dùng chung channel với classic, khác ở field `account` của lệnh spot;
+/// futures của unified account đặt lệnh như classic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AccountMode {
+    Classic,
+    Unified,
+}
+
+impl AccountMode {
+    fn spot_account(self) -> Option<String> {
+        match self {
+            AccountMode::Classic => None,
+            AccountMode::Unified => Some("unified".to_string()),
+        }
+    }
+}
+
+impl Market {
+    fn ws_url(self) -> &'static str {
+        match self {
+            Market::Spot => "wss://api.gateio.ws/ws/v4/",
+            Market::Futures => "wss://fx-ws.gateio.ws/v4/ws/usdt",
+        }
+    }
+
+    fn instrument_url(self, pair: &str) -> String {
+        match self {
+            Market::Spot => format!("https://api.gateio.ws/api/v4/spot/currency_pairs/{}", pair),
+            Market::Futures => format!("https://api.gateio.ws/api/v4/futures/usdt/contracts/{}", pair),
+        }
+    }
+
+    fn instruments_url(self) -> &'static str {
+        match self {
+            Market::Spot => "https://api.gateio.ws/api/v4/spot/currency_pairs",
+            Market::Futures => "https://api.gateio.ws/api/v4/futures/usdt/contracts",
+        }
+    }
+
+    /// Endpoint private đơn giản nhất (số dư) để kiểm tra key qua REST
+    fn accounts_path(self) -> &'static str {
+        match self {
+            Market::Spot => "/api/v4/spot/accounts",
+            Market::Futures => "/api/v4/futures/usdt/accounts",
+        }
+    }
+
+    /// Channel private cho --subscribe: nhận `orders` hoặc `spot.orders`, trả tên đầy đủ
+    fn private_channel(self, name: &str) -> Result<String, Error> {
+        let prefix = self.channel("");
+        let short = name.trim().strip_prefix(prefix.as_str()).unwrap_or(name.trim());
+        match short {
+            "orders" | "balances" | "usertrades" => Ok(self.channel(short)),
+            _ => Err(Error::Connect(format!(
+                "unsupported --subscribe channel {:?} (expected orders, balances or usertrades on {})",
+                name,
+                prefix.trim_end_matches('.')
+            ))),
+        }
+    }
+
+    /// Tên channel đầy đủ, ví dụ `channel("login")` -> `spot.login` / `futures.login`
+    fn channel(self, name: &str) -> String {
+        match self {
+            Market::Spot => format!("spot.{}", name),
+            Market::Futures => format!("futures.{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OrderParam {
+    Spot(SpotOrderParam),
+    Futures(FuturesOrderParam),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpotOrderParam {
+    currency_pair: String,
+    side: Side,
+    #[serde(rename = "type")]
+    order_type: OrderType,
+    amount: OrderNumber,
+    price: OrderNumber,
+    time_in_force: TimeInForce,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    // "unified" với --account-mode unified, không gửi thì server dùng tài khoản spot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    account: Option<String>,
+}
+
+/// Futures không có field side/type: size âm là bán, price "0" + ioc là lệnh market
+#[derive(Debug, Serialize, Deserialize)]
+struct FuturesOrderParam {
+    contract: String,
+    size: i64,
+    price: OrderNumber,
+    tif: TimeInForce,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AmendRequest {
+    time: u64,
+    channel: String,
+    event: String,
+    payload: AmendPayload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AmendPayload {
+    req_id: String,
+    req_param: AmendParam,
+    #[serde(flatten)]
+    auth: Option<RequestAuth>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AmendParam {
+    order_id: String,
+    currency_pair: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    price: Option<OrderNumber>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<OrderNumber>,
+}
+
+/// Việc cần gửi tiếp trên cùng connection sau khi xử lý một message
+#[derive(Debug, Clone, PartialEq)]
+enum FollowUp {
+    Amend { order_id: String, currency_pair: String },
+    // Login xong, subscribe các channel của --subscribe
+    SubscribePrivate,
+    // Login lỗi timestamp, ký lại với timestamp mới
+    RetryAuth,
+    // --single-connection: frame của channel orderbook, chuyển cho orderbook task
+    RelayBook(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Side {
+    Buy,
+    Sell,
+}
+
+impl FromStr for Side {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "buy" => Ok(Side::Buy),
+            "sell" => Ok(Side::Sell),
+            _ => Err(Error::Order(format!("Invalid order side {:?} (expected buy or sell)", s))),
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Side::Buy => "BUY",
+            Side::Sell => "SELL",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OrderType {
+    Limit,
+    Market,
+}
+
+impl FromStr for OrderType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "limit" => Ok(OrderType::Limit),
+            "market" => Ok(OrderType::Market),
+            _ => Err(Error::Order(format!("Invalid order type {:?} (expected limit or market)", s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TimeInForce {
+    Gtc,
+    Ioc,
+    Poc,
+    Fok,
+}
+
+impl FromStr for TimeInForce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.to_lowercase().as_str() {
+            "gtc" => Ok(TimeInForce::Gtc),
+            "ioc" => Ok(TimeInForce::Ioc),
+            "poc" => Ok(TimeInForce::Poc),
+            "fok" => Ok(TimeInForce::Fok),
+            _ => Err(Error::Order(format!("Invalid time_in_force {:?} (expected gtc, ioc, poc or fok)", s))),
+        }
+    }
+}
+
+/// Thời điểm lấy cùng lúc ở dạng `Instant` (để tính latency) và nanosecond
+/// tuyệt đối (CLOCK_MONOTONIC + wall clock) để đối chiếu với tcpdump/eBPF
+#[derive(Debug, Clone, Copy)]
+struct Timestamp {
+    instant: Instant,
+    mono_ns: u64,
+    wall_ns: u64,
+    // Theo --reference-clock, nếu có
+    ref_ns: Option<u64>,
+}
+
+impl Timestamp {
+    fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            mono_ns: monotonic_ns(),
+            wall_ns: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+            ref_ns: reference_clock().and_then(|clock| clock.now_ns()),
+        }
+    }
+}
+
+/// Nguồn thời gian tham chiếu để so sánh kết quả giữa nhiều máy. Giả định: các máy
+/// cùng đồng bộ clock này với một master (PTP/chrony) và sai số đồng bộ nhỏ hơn
+/// latency cần đo; `Instant` vẫn là nguồn duy nhất để tính latency trong một máy
+trait Clock: Send + Sync {
+    fn name(&self) -> &str;
+    /// Nanosecond theo clock tham chiếu, None nếu đọc lỗi
+    fn now_ns(&self) -> Option<u64>;
+}
+
+/// Clock tham chiếu của process, cài một lần từ --reference-clock trước khi chạy
+static REFERENCE_CLOCK: OnceLock<Box<dyn Clock>> = OnceLock::new();
+
+fn reference_clock() -> Option<&'static dyn Clock> {
+    REFERENCE_CLOCK.get().map(|clock| clock.as_ref())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClockSpec {
+    Realtime,
+    Tai,
+    MonotonicRaw,
+    Ptp(PathBuf),
+}
+
+impl FromStr for ClockSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "realtime" => Ok(ClockSpec::Realtime),
+            "tai" => Ok(ClockSpec::Tai),
+            "monotonic-raw" => Ok(ClockSpec::MonotonicRaw),
+            other => match other.strip_prefix("ptp:") {
+                Some(path) if !path.is_empty() => Ok(ClockSpec::Ptp(PathBuf::from(path))),
+                _ => Err(format!("unknown clock {:?} (expected realtime, tai, monotonic-raw or ptp:/dev/ptpN)", s)),
+            },
+        }
+    }
+}
+
+/// Clock POSIX đọc qua clock_gettime; `_device` giữ fd của /dev/ptpN mở suốt run
+#[cfg(unix)]
+struct PosixClock {
+    name: String,
+    id: libc::clockid_t,
+    _device: Option<File>,
+}
+
+#[cfg(unix)]
+impl Clock for PosixClock {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn now_ns(&self) -> Option<u64> {
+        let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        // SAFETY: `ts` là buffer hợp lệ; clock id không hợp lệ chỉ làm hàm trả -1
+        let rc = unsafe { libc::clock_gettime(self.id, &mut ts) };
+        (rc == 0).then(|| ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64)
+    }
+}
+
+impl ClockSpec {
+    #[cfg(unix)]
+    fn open(&self) -> Result<Box<dyn Clock>> {
+        let (name, id, device) = match self {
+            ClockSpec::Realtime => ("realtime".to_string(), libc::CLOCK_REALTIME, None),
+            #[cfg(target_os = "linux")]
+            ClockSpec::Tai => ("tai".to_string(), libc::CLOCK_TAI, None),
+            #[cfg(target_os = "linux")]
+            ClockSpec::MonotonicRaw => ("monotonic-raw".to_string(), libc::CLOCK_MONOTONIC_RAW, None),
+            #[cfg(target_os = "linux")]
+            ClockSpec::Ptp(path) => {
+                use std::os::fd::AsRawFd;
+                let device = File::open(path).with_context(|| format!("Failed to open PTP clock {}", path.display()))?;
+                // FD_TO_CLOCKID của kernel: dynamic clock id từ fd của /dev/ptpN
+                let id = ((!device.as_raw_fd()) << 3) | 3;
+                (format!("ptp:{}", path.display()), id as libc::clockid_t, Some(device))
+            }
+            #[cfg(not(target_os = "linux"))]
+            other => anyhow::bail!("reference clock {:?} is only supported on Linux", other),
+        };
+        let clock = PosixClock { name, id, _device: device };
+        if clock.now_ns().is_none() {
+            anyhow::bail!("reference clock {} cannot be read: {}", clock.name, std::io::Error::last_os_error());
+        }
+        Ok(Box::new(clock))
+    }
+
+    #[cfg(not(unix))]
+    fn open(&self) -> Result<Box<dyn Clock>> {
+        anyhow::bail!("--reference-clock is only supported on unix")
+    }
+}
+
+/// --trace-order: các mốc thời gian của một lệnh theo thứ tự xảy ra. "frame received"
+/// là lúc tungstenite trả về frame hoàn chỉnh (không có mốc byte đầu tiên)
+#[derive(Debug, Default)]
+struct OrderTrace {
+    req_id: Option<String>,
+    events: Vec<(String, Timestamp)>,
+    done: bool,
+}
+
+impl OrderTrace {
+    fn mark(&mut self, label: impl Into<String>, at: Timestamp) {
+        self.events.push((label.into(), at));
+    }
+
+    fn print(&self) {
+        // Per-message auth bấm giờ trước khi ký nên sắp lại theo thời điểm
+        let mut events: Vec<&(String, Timestamp)> = self.events.iter().collect();
+        events.sort_by_key(|(_, at)| at.instant);
+        let Some(first) = events.first() else {
+            return;
+        };
+        report!("\n🔬 ORDER TRACE {}:", self.req_id.as_deref().unwrap_or("-"));
+        let mut previous = first.1.instant;
+        for (label, at) in events.iter().copied() {
+            let wall = DateTime::<Utc>::from_timestamp_nanos(at.wall_ns as i64);
+            report!(
+                "   {:<36} {}  +{:>9.3} ms  (Δ {:>8.3} ms)",
+                label,
+                wall.format("%H:%M:%S%.6f"),
+                at.instant.duration_since(first.1.instant).as_secs_f64() * 1000.0,
+                at.instant.duration_since(previous).as_secs_f64() * 1000.0
+            );
+            previous = at.instant;
+        }
+    }
+}
+
+#[cfg(unix)]
+fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: `ts` là buffer hợp lệ, CLOCK_MONOTONIC luôn tồn tại trên unix
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+#[cfg(not(unix))]
+fn monotonic_ns() -> u64 {
+    0
+}
+
+/// Một phản hồi của lệnh (ack hoặc result), gửi tới các `LatencySink`
+#[derive(Debug, Clone)]
+struct OrderResponse {
+    req_id: String,
+    conn: usize,
+    pair: String,
+    response_num: u32,
+    status: String,
+    latency_ms: f64,
+    // Thời gian xử lý do server báo, None nếu response không có
+    server_ms: Option<f64>,
+    size_bytes: usize,
+    rate: Option<f64>,
+    fill: Option<Fill>,
+    side: Option<Side>,
+    sent: Timestamp,
+    received: Timestamp,
+}
+
+/// Thông tin khớp lệnh trong result của order_place; giữ nguyên chuỗi số của server
+#[derive(Debug, Clone, Default)]
+struct Fill {
+    // Spot: avg_deal_price (fill_price ở bản cũ), futures: fill_price
+    price: Option<String>,
+    filled_total: Option<String>,
+    // Spot: amount, futures: size
+    amount: Option<String>,
+    left: Option<String>,
+}
+
+impl Fill {
+    fn from_result(result: Option<&Value>) -> Option<Self> {
+        let result = result?;
+        let field = |keys: &[&str]| {
+            keys.iter().find_map(|key| match result.get(*key) {
+                Some(Value::String(v)) if !v.is_empty() => Some(v.clone()),
+                Some(Value::Number(v)) => Some(v.to_string()),
+                _ => None,
+            })
+        };
+        let fill = Self {
+            price: field(&["avg_deal_price", "fill_price"]),
+            filled_total: field(&["filled_total"]),
+            amount: field(&["amount", "size"]),
+            left: field(&["left"]),
+        };
+        (fill.price.is_some() || fill.amount.is_some()).then_some(fill)
+    }
+
+    /// Khối lượng đã khớp = amount - left (trị tuyệt đối vì size futures âm với lệnh bán)
+    fn filled_amount(&self) -> Option<f64> {
+        let amount: f64 = self.amount.as_deref()?.parse().ok()?;
+        let left: f64 = self.left.as_deref().unwrap_or("0").parse().ok()?;
+        Some((amount.abs() - left.abs()).max(0.0))
+    }
+
+    fn csv_fields(fill: Option<&Fill>) -> String {
+        let fill = fill.cloned().unwrap_or_default();
+        [fill.price, fill.filled_total, fill.amount, fill.left].map(Option::unwrap_or_default).join(",")
+    }
+}
+
+impl OrderResponse {
+    fn csv_header(trace: bool) -> String {
+        let mut header = "req_id,conn,pair,response_num,status,latency_ms,server_ms,size_bytes,rate,fill_price,filled_total,amount,left".to_string();
+        if trace {
+            header.push_str(",send_mono_ns,send_wall_ns,recv_mono_ns,recv_wall_ns");
+        }
+        if reference_clock().is_some() {
+            header.push_str(",send_ref_ns,recv_ref_ns");
+        }
+        header
+    }
+
+    fn csv_row(&self, trace: bool) -> String {
+        let mut row = format!(
+            "{},{},{},{},{},{:.6},{},{},{},{}",
+            self.req_id,
+            self.conn,
+            self.pair,
+            self.response_num,
+            self.status,
+            self.latency_ms,
+            self.server_ms.map_or(String::new(), |ms| format!("{:.3}", ms)),
+            self.size_bytes,
+            self.rate.map_or(String::new(), |r| r.to_string()),
+            Fill::csv_fields(self.fill.as_ref())
+        );
+        if trace {
+            row.push_str(&format!(
+                ",{},{},{},{}",
+                self.sent.mono_ns, self.sent.wall_ns, self.received.mono_ns, self.received.wall_ns
+            ));
+        }
+        if reference_clock().is_some() {
+            let field = |ns: Option<u64>| ns.map_or(String::new(), |ns| ns.to_string());
+            row.push_str(&format!(",{},{}", field(self.sent.ref_ns), field(self.received.ref_ns)));
+        }
+        row
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SinkSpec {
+    Stdout,
+    Csv(PathBuf),
+    JsonLines(PathBuf),
+    Statsd(String),
+    None,
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, path) = match s.split_once(':') {
+            Some((kind, path)) => (kind, Some(PathBuf::from(path))),
+            None => (s, None),
+        };
+        match (kind, path) {
+            ("stdout", None) => Ok(SinkSpec::Stdout),
+            ("none", None) => Ok(SinkSpec::None),
+            ("csv", Some(path)) => Ok(SinkSpec::Csv(path)),
+            ("jsonl", Some(path)) => Ok(SinkSpec::JsonLines(path)),
+            ("statsd", Some(addr)) => Ok(SinkSpec::Statsd(addr.to_string_lossy().into_owned())),
+            ("statsd", None) => Err("sink \"statsd\" needs an address, e.g. statsd:127.0.0.1:8125".to_string()),
+            ("csv" | "jsonl", None) => Err(format!("sink {:?} needs a path, e.g. {}:out.{}", kind, kind, kind)),
+            _ => Err(format!("unknown sink {:?} (expected stdout, csv:PATH, jsonl:PATH, statsd:HOST:PORT or none)", s)),
+        }
+    }
+}
+
+/// Nơi nhận kết quả latency của từng phản hồi; thêm output mới (statsd, Kafka...)
+/// chỉ cần implement trait này
+trait LatencySink: Send + fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    fn record(&mut self, response: &OrderResponse) -> std::io::Result<()>;
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct StdoutSink {
+    dp: usize,
+}
+
+impl LatencySink for StdoutSink {
+    fn name(&self) -> &'static str {
+        "stdout"
+    }
+
+    fn record(&mut self, r: &OrderResponse) -> std::io::Result<()> {
+        let dp = self.dp;
+        report!(
+            "🧾 req_id={} conn={} pair={} response={} status={} latency_ms={:.dp$}",
+            r.req_id, r.conn, r.pair, r.response_num, r.status, r.latency_ms
+        );
+        Ok(())
+    }
+}
+
+/// Kích thước dạng `50MB`, `512K`, `1G` hoặc số byte
+fn parse_size(s: &str) -> Result<u64, String> {
+    let upper = s.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier: u64 = match &upper[digits.len()..] {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        unit => return Err(format!("unknown size unit {:?} (expected B, KB, MB or GB)", unit)),
+    };
+    let value: u64 = digits.trim().parse().map_err(|_| format!("invalid size {:?}", s))?;
+    value
+        .checked_mul(multiplier)
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| format!("size {:?} must be between 1 byte and {} bytes", s, u64::MAX))
+}
+
+/// Thời lượng dạng `500ms`, `2s`, `1.5s` hoặc số ms
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let (value, scale) = if let Some(ms) = lower.strip_suffix("ms") {
+        (ms, 1e-3)
+    } else if let Some(secs) = lower.strip_suffix('s') {
+        (secs, 1.0)
+    } else {
+        (lower.as_str(), 1e-3)
+    };
+    let value: f64 = value.trim().parse().map_err(|_| format!("invalid duration {:?} (expected e.g. 500ms or 2s)", s))?;
+    if !value.is_finite() || value <= 0.0 {
+        return Err(format!("duration {:?} must be positive", s));
+    }
+    Ok(Duration::from_secs_f64(value * scale))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct RotationPolicy {
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+/// File output chia segment theo kích thước/thời gian; segment cũ được nén gzip
+/// ở thread riêng để không chặn đường nhận phản hồi. Chỉ rotate giữa các dòng,
+/// header (nếu có) được ghi lại ở đầu mỗi segment
+#[derive(Debug)]
+struct RotatingWriter {
+    path: PathBuf,
+    header: Option<String>,
+    policy: RotationPolicy,
+    writer: BufWriter<File>,
+    written: u64,
+    opened_at: Instant,
+    segment: u32,
+}
+
+impl RotatingWriter {
+    /// Ghi tiếp vào cuối file có sẵn; header chỉ được ghi khi file mới hoặc rỗng
+    fn create(path: &PathBuf, header: Option<String>, policy: RotationPolicy) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let existing = file.metadata()?.len();
+        let mut writer = Self {
+            path: path.clone(),
+            header,
+            policy,
+            writer: BufWriter::new(file),
+            written: existing,
+            opened_at: Instant::now(),
+            segment: Self::last_segment(path),
+        };
+        if existing == 0 {
+            writer.write_header()?;
+        }
+        Ok(writer)
+    }
+
+    /// Số segment lớn nhất đã có cạnh `path` (PATH.N hoặc PATH.N.gz của run trước),
+    /// để run mới đánh số tiếp thay vì ghi đè PATH.1.gz
+    fn last_segment(path: &std::path::Path) -> u32 {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+            return 0;
+        };
+        let dir = if dir.as_os_str().is_empty() { std::path::Path::new(".") } else { dir };
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return 0;
+        };
+        entries
+            .filter_map(|entry| {
+                let file_name = entry.ok()?.file_name();
+                let index = file_name.to_str()?.strip_prefix(name)?.strip_prefix('.')?;
+                index.strip_suffix(".gz").unwrap_or(index).parse::<u32>().ok()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        if let Some(header) = &self.header {
+            writeln!(self.writer, "{}", header)?;
+            self.written += header.len() as u64 + 1;
+        }
+        Ok(())
+    }
+
+    fn due(&self) -> bool {
+        let header_len = self.header.as_ref().map_or(0, |h| h.len() as u64 + 1);
+        let has_rows = self.written > header_len;
+        has_rows
+            && (self.policy.max_bytes.is_some_and(|max| self.written >= max)
+                || self.policy.max_age.is_some_and(|max| self.opened_at.elapsed() >= max))
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.segment += 1;
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{}", self.segment));
+        let rotated = PathBuf::from(rotated);
+        std::fs::rename(&self.path, &rotated)?;
+        self.writer = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        self.opened_at = Instant::now();
+        self.write_header()?;
+
+        std::thread::spawn(move || {
+            if let Err(e) = gzip_file(&rotated) {
+                error!("Failed to compress {}: {}", rotated.display(), e);
+            }
+        });
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.due() {
+            self.rotate()?;
+        }
+        writeln!(self.writer, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Nén PATH thành PATH.gz rồi xoá bản gốc
+fn gzip_file(path: &PathBuf) -> std::io::Result<()> {
+    let mut gz_path = path.clone().into_os_string();
+    gz_path.push(".gz");
+    let mut input = File::open(path)?;
+    let mut encoder = flate2::write::GzEncoder::new(BufWriter::new(File::create(&gz_path)?), flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.flush()?;
+    std::fs::remove_file(path)
+}
+
+#[derive(Debug)]
+struct CsvSink {
+    writer: RotatingWriter,
+    trace: bool,
+}
+
+impl CsvSink {
+    fn create(path: &PathBuf, trace: bool, policy: RotationPolicy) -> Result<Self> {
+        let writer = RotatingWriter::create(path, Some(OrderResponse::csv_header(trace)), policy)
+            .with_context(|| format!("Failed to create CSV file {}", path.display()))?;
+        Ok(Self { writer, trace })
+    }
+}
+
+impl LatencySink for CsvSink {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn record(&mut self, response: &OrderResponse) -> std::io::Result<()> {
+        self.writer.write_line(&response.csv_row(self.trace))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug)]
+struct JsonLinesSink {
+    writer: RotatingWriter,
+    trace: bool,
+}
+
+impl LatencySink for JsonLinesSink {
+    fn name(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn record(&mut self, r: &OrderResponse) -> std::io::Result<()> {
+        let mut record = serde_json::json!({
+            "req_id": r.req_id,
+            "conn": r.conn,
+            "pair": r.pair,
+            "response_num": r.response_num,
+            "status": r.status,
+            "latency_ms": r.latency_ms,
+            "server_ms": r.server_ms,
+            "size_bytes": r.size_bytes,
+            "rate": r.rate,
+        });
+        if let Some(fill) = &r.fill {
+            record["fill_price"] = fill.price.clone().into();
+            record["filled_total"] = fill.filled_total.clone().into();
+            record["amount"] = fill.amount.clone().into();
+            record["left"] = fill.left.clone().into();
+        }
+        if self.trace {
+            record["send_mono_ns"] = r.sent.mono_ns.into();
+            record["send_wall_ns"] = r.sent.wall_ns.into();
+            record["recv_mono_ns"] = r.received.mono_ns.into();
+            record["recv_wall_ns"] = r.received.wall_ns.into();
+        }
+        if reference_clock().is_some() {
+            record["send_ref_ns"] = r.sent.ref_ns.into();
+            record["recv_ref_ns"] = r.received.ref_ns.into();
+        }
+        self.writer.write_line(&record.to_string())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[derive(Debug)]
+struct NullSink;
+
+impl LatencySink for NullSink {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn record(&mut self, _response: &OrderResponse) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// DogStatsD qua UDP: mỗi phản hồi là 1 timing metric, không chặn đường nhận phản hồi
+/// (socket non-blocking, gói bị drop khi buffer đầy hoặc chưa có agent lắng nghe)
+#[derive(Debug)]
+struct StatsdSink {
+    socket: std::net::UdpSocket,
+    account: String,
+}
+
+impl StatsdSink {
+    fn connect(addr: &str, account: &str) -> std::io::Result<Self> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket, account: account.to_string() })
+    }
+}
+
+impl LatencySink for StatsdSink {
+    fn name(&self) -> &'static str {
+        "statsd"
+    }
+
+    fn record(&mut self, r: &OrderResponse) -> std::io::Result<()> {
+        let side = r.side.map_or("none".to_string(), |side| side.to_string().to_lowercase());
+        let response = if r.response_num == 1 { "ack" } else { "result" };
+        let packet = format!(
+            "gateio.order.latency:{:.3}|ms|#symbol:{},account:{},side:{},response:{},status:{}",
+            r.latency_ms, r.pair, self.account, side, response, r.status
+        );
+        match self.socket.send(packet.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::ConnectionRefused) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn build_sinks(args: &Args, account_name: &str) -> Result<Vec<Box<dyn LatencySink>>> {
+    let mut specs = args.sinks.clone();
+    if let Some(path) = &args.csv {
+        specs.push(SinkSpec::Csv(path.clone()));
+    }
+    if let Some(addr) = &args.statsd {
+        specs.push(SinkSpec::Statsd(addr.clone()));
+    }
+    let policy = RotationPolicy {
+        max_bytes: args.rotate_size,
+        max_age: args.rotate_interval.map(Duration::from_secs),
+    };
+    let mut sinks: Vec<Box<dyn LatencySink>> = Vec::new();
+    for spec in specs {
+        sinks.push(match spec {
+            SinkSpec::Stdout => Box::new(StdoutSink { dp: args.dp() }),
+            SinkSpec::Csv(path) => Box::new(CsvSink::create(&path, args.trace_timestamps, policy)?),
+            SinkSpec::JsonLines(path) => {
+                let writer = RotatingWriter::create(&path, None, policy)
+                    .with_context(|| format!("Failed to create JSONL file {}", path.display()))?;
+                Box::new(JsonLinesSink { writer, trace: args.trace_timestamps })
+            }
+            SinkSpec::Statsd(addr) => Box::new(
+                StatsdSink::connect(&addr, account_name).with_context(|| format!("Failed to set up statsd sink {}", addr))?,
+            ),
+            SinkSpec::None => Box::new(NullSink),
+        });
+    }
+    Ok(sinks)
+}
+
+#[derive(Debug, Clone)]
+struct PendingOrder {
+    sent_time: Instant,
+    sent: Timestamp,
+    conn: usize,
+    pair: String,
+    // None với amend
+    side: Option<Side>,
+    // Rate (orders/sec) của rate limiter lúc gửi, nếu có bật --rate/--ramp
+    rate: Option<f64>,
+    // Id của quyết định chung khi --compare-symbols
+    decision: Option<u64>,
+    // Số thứ tự bước (từ 1) khi chạy --scenario
+    step: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct LatencySample {
+    conn: usize,
+    pair: String,
+    side: Option<Side>,
+    // Độ dài message phản hồi (bytes)
+    size: usize,
+    response_num: u32,
+    latency_ms: f64,
+    rate: Option<f64>,
+    decision: Option<u64>,
+    step: Option<usize>,
+}
+
+/// Lệnh không bao giờ nhận được phản hồi cuối (timeout hoặc mất connection)
+#[derive(Debug, Clone)]
+struct LostOrder {
+    conn: usize,
+    pair: String,
+    reason: &'static str,
+}
+
+/// Sự kiện vận hành của run (ngoài latency), in cùng summary lúc shutdown
+#[derive(Debug)]
+struct OpsStats {
+    started: Instant,
+    reconnects: u64,
+    auth_failures: u64,
+    failures: BTreeMap<GateError, u64>,
+    pongs: u64,
+    // RTT (ms) của từng ping -> pong
+    ping_rtt: Vec<f64>,
+}
+
+impl OpsStats {
+    fn new() -> Self {
+        Self { started: Instant::now(), reconnects: 0, auth_failures: 0, failures: BTreeMap::new(), pongs: 0, ping_rtt: Vec::new() }
+    }
+
+    fn print(&self) {
+        let uptime = self.started.elapsed().as_secs();
+        report!("\n📊 OPERATIONS:");
+        report!("   ⏳ Uptime: {}h{:02}m{:02}s", uptime / 3600, uptime / 60 % 60, uptime % 60);
+        report!("   🔄 Reconnects: {}", self.reconnects);
+        report!("   🔐 Auth failures: {}", self.auth_failures);
+        report!("   📡 Ping/pong round-trips: {}", self.pongs);
+        if let Some(line) = LatencyStats::describe(&mut self.ping_rtt.clone(), 1) {
+            report!("   ⏱ Ping RTT: {}", line);
+        }
+        if self.failures.is_empty() {
+            report!("   ✅ Order failures: 0");
+        } else {
+            let by_kind: Vec<String> = self.failures.iter().map(|(kind, n)| format!("{:?} {}", kind, n)).collect();
+            report!("   ❌ Order failures: {} ({})", self.failures.values().sum::<u64>(), by_kind.join(", "));
+        }
+    }
+}
+
+/// Latency samples của mọi lệnh trong run, dùng cho tổng kết theo connection
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    samples: Vec<LatencySample>,
+    amend_samples: Vec<f64>,
+    lost: Vec<LostOrder>,
+    // Sức khoẻ feed: số update có book bị cross (bid >= ask) hoặc giá 0
+    crossed_books: u64,
+    zero_books: u64,
+    // --measure-book-latency: độ trễ feed (ms) của từng update
+    book_latency: Vec<f64>,
+}
+
+impl LatencyStats {
+    fn record(&mut self, pending: &PendingOrder, response_num: u32, latency_ms: f64, size: usize) {
+        self.samples.push(LatencySample {
+            conn: pending.conn,
+            pair: pending.pair.clone(),
+            side: pending.side,
+            size,
+            response_num,
+            latency_ms,
+            rate: pending.rate,
+            decision: pending.decision,
+            step: pending.step,
+        });
+    }
+
+    fn record_amend(&mut self, latency_ms: f64) {
+        self.amend_samples.push(latency_ms);
+    }
+
+    fn record_lost(&mut self, pending: &PendingOrder, reason: &'static str) {
+        self.lost.push(LostOrder { conn: pending.conn, pair: pending.pair.clone(), reason });
+    }
+
+    fn record_book_latency(&mut self, latency_ms: f64) {
+        self.book_latency.push(latency_ms);
+    }
+
+    fn print_feed_health(&self) {
+        if self.crossed_books > 0 || self.zero_books > 0 {
+            report!(
+                "\n📉 Feed health: {} crossed and {} zero-price book update(s)",
+                self.crossed_books,
+                self.zero_books
+            );
+        }
+        let mut book_latency = self.book_latency.clone();
+        if let Some(line) = Self::describe(&mut book_latency, 1) {
+            report!("\n📡 Book feed latency (receive - server t): {}", line);
+        }
+    }
+
+    fn print_lost(&self, connections: usize) {
+        if self.lost.is_empty() {
+            return;
+        }
+        report!("\n📊 ORDERS WITHOUT FINAL RESPONSE: {}", self.lost.len());
+        let timed_out = self.lost.iter().filter(|l| l.reason == DEADLINE_REASON).count();
+        if timed_out > 0 {
+            report!("   ⏰ Timed out (no ack within --order-deadline): {}", timed_out);
+        }
+        for conn in 0..connections {
+            let lost: Vec<&LostOrder> = self.lost.iter().filter(|l| l.conn == conn).collect();
+            if lost.is_empty() {
+                continue;
+            }
+            let mut reasons: Vec<&str> = lost.iter().map(|l| l.reason).collect();
+            reasons.sort();
+            reasons.dedup();
+            let mut pairs: Vec<&str> = lost.iter().map(|l| l.pair.as_str()).collect();
+            pairs.sort();
+            pairs.dedup();
+            report!("   ⌛ Connection #{}: {} ({}; {})", conn, lost.len(), reasons.join(", "), pairs.join(", "));
+        }
+    }
+
+    fn percentile(sorted: &[f64], p: f64) -> f64 {
+        sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+    }
+
+    fn describe(values: &mut [f64], dp: usize) -> Option<String> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| Self::percentile(values, p);
+        let avg = values.iter().sum::<f64>() / values.len() as f64;
+        Some(format!(
+            "n={} min={:.dp$} avg={:.dp$} p50={:.dp$} p95={:.dp$} max={:.dp$} ms",
+            values.len(),
+            values[0],
+            avg,
+            percentile(0.50),
+            percentile(0.95),
+            values[values.len() - 1]
+        ))
+    }
+
+    fn size_bucket(size: usize) -> &'static str {
+        match size {
+            0..=1023 => "< 1KB",
+            1024..=4096 => "1-4KB",
+            _ => "> 4KB",
+        }
+    }
+
+    fn print_group(&self, title: &str, dp: usize, filter: impl Fn(&LatencySample) -> bool) {
+        report!("   {}", title);
+        for response_num in 1..=2 {
+            let mut values: Vec<f64> = self
+                .samples
+                .iter()
+                .filter(|s| s.response_num == response_num && filter(s))
+                .map(|s| s.latency_ms)
+                .collect();
+            if let Some(line) = Self::describe(&mut values, dp) {
+                report!("      ⏱ Response {} ({} latency): {}", response_num, response_label(response_num), line);
+            }
+        }
+    }
+
+    /// Latency (ms) của mọi lệnh tới Response `response_num` (1 = receipt, 2 = result),
+    /// theo thứ tự nhận
+    pub fn latencies(&self, response_num: u32) -> Vec<f64> {
+        self.samples.iter().filter(|s| s.response_num == response_num).map(|s| s.latency_ms).collect()
+    }
+
+    /// Số lệnh không bao giờ nhận được phản hồi cuối (timeout hoặc mất connection)
+    pub fn lost_count(&self) -> usize {
+        self.lost.len()
+    }
+
+    /// p95 latency (ms) tới Response `response_num`, None khi chưa có sample
+    pub fn p95(&self, response_num: u32) -> Option<f64> {
+        let mut values = self.latencies(response_num);
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        Some(Self::percentile(&values, 0.95))
+    }
+
+    /// --max-p95-ms: Err khi p95 của response được chọn vượt ngưỡng hoặc không có sample
+    fn check_p95(&self, target: LatencyTarget, max_ms: f64, dp: usize) -> Result<()> {
+        let response_num = target.response_num();
+        let label = response_label(response_num);
+        match self.p95(response_num) {
+            Some(p95) if p95 <= max_ms => {
+                report!("\n✅ p95 {} latency {:.dp$} ms <= {} ms", label, p95, max_ms);
+                Ok(())
+            }
+            Some(p95) => {
+                report!("\n❌ p95 {} latency {:.dp$} ms > {} ms", label, p95, max_ms);
+                anyhow::bail!("p95 {} latency {:.dp$} ms exceeds --max-p95-ms {}", label, p95, max_ms)
+            }
+            None => anyhow::bail!("--max-p95-ms: no Response {} ({}) samples recorded", response_num, label),
+        }
+    }
+
+    /// In tổng kết latency theo connection (và theo cặp, size, step...) ra stdout,
+    /// `dp` là số chữ số thập phân của giá trị ms
+    pub fn print_summary(&self, connections: usize, dp: usize) {
+        if self.samples.is_empty() {
+            report!("\n📊 No latency samples recorded");
+            self.print_lost(connections);
+            self.print_feed_health();
+            return;
+        }
+        report!("\n📊 LATENCY SUMMARY BY CONNECTION:");
+        if connections > 1 {
+            for conn in 0..connections {
+                self.print_group(&format!("🔌 Connection #{}", conn), dp, |s| s.conn == conn);
+            }
+        }
+        self.print_group("🌐 Combined", dp, |_| true);
+
+        let mut rates: Vec<f64> = self.samples.iter().filter_map(|s| s.rate).collect();
+        rates.sort_by(|a, b| a.total_cmp(b));
+        rates.dedup();
+        if rates.len() > 1 {
+            report!("\n📊 LATENCY BY RATE LEVEL:");
+            for rate in rates {
+                self.print_group(&format!("🚦 {} orders/s", rate), dp, |s| s.rate == Some(rate));
+            }
+        }
+
+        if self.samples.iter().any(|s| s.side == Some(Side::Sell)) {
+            report!("\n📊 LATENCY BY SIDE (BUY @ ask vs SELL @ bid):");
+            for side in [Side::Buy, Side::Sell] {
+                self.print_group(&format!("⚖️ {}", side), dp, |s| s.side == Some(side));
+            }
+        }
+
+        report!("\n📊 LATENCY BY RESPONSE SIZE:");
+        for bucket in ["< 1KB", "1-4KB", "> 4KB"] {
+            if self.samples.iter().any(|s| Self::size_bucket(s.size) == bucket) {
+                self.print_group(&format!("📦 {}", bucket), dp, |s| Self::size_bucket(s.size) == bucket);
+            }
+        }
+
+        let mut amend = self.amend_samples.clone();
+        if let Some(line) = Self::describe(&mut amend, dp) {
+            report!("   ✏️ Amend ack: {}", line);
+        }
+
+        let mut steps: Vec<usize> = self.samples.iter().filter_map(|s| s.step).collect();
+        steps.sort();
+        steps.dedup();
+        if !steps.is_empty() {
+            report!("\n📊 LATENCY BY SCENARIO STEP:");
+            for step in steps {
+                self.print_group(&format!("📜 Step {}", step), dp, |s| s.step == Some(step));
+            }
+        }
+
+        self.print_pair_matrix(dp);
+        self.print_paired(dp);
+        self.print_lost(connections);
+        self.print_feed_health();
+    }
+
+    /// --compare-symbols: so sánh từng cặp lệnh sinh ra từ cùng một quyết định
+    fn print_paired(&self, dp: usize) {
+        let mut pairs: Vec<&str> = self.samples.iter().filter(|s| s.decision.is_some()).map(|s| s.pair.as_str()).collect();
+        pairs.sort();
+        pairs.dedup();
+        let [a, b] = pairs[..] else {
+            return;
+        };
+
+        report!("\n📊 PAIRED COMPARISON {} vs {} (same decision):", a, b);
+        for response_num in 1..=2 {
+            let mut by_decision: HashMap<(u64, usize), (Option<f64>, Option<f64>)> = HashMap::new();
+            for s in self.samples.iter().filter(|s| s.response_num == response_num) {
+                let Some(decision) = s.decision else {
+                    continue;
+                };
+                let entry = by_decision.entry((decision, s.conn)).or_default();
+                if s.pair == a {
+                    entry.0 = Some(s.latency_ms);
+                } else {
+                    entry.1 = Some(s.latency_ms);
+                }
+            }
+            let mut diffs: Vec<f64> = by_decision.values().filter_map(|(a, b)| Some(a.as_ref()? - b.as_ref()?)).collect();
+            let a_faster = diffs.iter().filter(|d| **d < 0.0).count();
+            let b_faster = diffs.iter().filter(|d| **d > 0.0).count();
+            if let Some(line) = Self::describe(&mut diffs, dp) {
+                report!("   ⏱ Response {}: {} faster {}x, {} faster {}x", response_num, a, a_faster, b, b_faster);
+                report!("      Δ ({} - {}): {}", a, b, line);
+            }
+        }
+    }
+
+    /// Bảng so sánh theo cặp, sắp xếp theo p50 của ack (Response 1) tăng dần
+    fn print_pair_matrix(&self, dp: usize) {
+        let mut pairs: Vec<&str> = self.samples.iter().map(|s| s.pair.as_str()).collect();
+        pairs.sort();
+        pairs.dedup();
+        if pairs.len() < 2 {
+            return;
+        }
+
+        let column = |pair: &str, response_num: u32| -> Vec<f64> {
+            let mut values: Vec<f64> = self
+                .samples
+                .iter()
+                .filter(|s| s.pair == pair && s.response_num == response_num)
+                .map(|s| s.latency_ms)
+                .collect();
+            values.sort_by(|a, b| a.total_cmp(b));
+            values
+        };
+        let cell = |values: &[f64], p: f64| {
+            if values.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{:.dp$}", Self::percentile(values, p))
+            }
+        };
+
+        let mut rows: Vec<(&str, Vec<f64>, Vec<f64>)> =
+            pairs.into_iter().map(|pair| (pair, column(pair, 1), column(pair, 2))).collect();
+        rows.sort_by(|a, b| {
+            let p50 = |v: &[f64]| if v.is_empty() { f64::INFINITY } else { Self::percentile(v, 0.50) };
+            p50(&a.1).total_cmp(&p50(&b.1))
+        });
+
+        report!("\n📊 LATENCY MATRIX BY PAIR (sorted by Response 1 p50):");
+        report!("   {:<14} {:>5} {:>12} {:>12} {:>12} {:>12}", "pair", "n", "r1 p50", "r1 p95", "r2 p50", "r2 p95");
+        for (pair, r1, r2) in rows {
+            report!(
+                "   {:<14} {:>5} {:>12} {:>12} {:>12} {:>12}",
+                pair,
+                r1.len().max(r2.len()),
+                cell(&r1, 0.50),
+                cell(&r1, 0.95),
+                cell(&r2, 0.50),
+                cell(&r2, 0.95)
+            );
+        }
+    }
+}
+
+/// req_id đã finalize gần đây nhất (tối đa FINALIZED_MEMORY), bỏ cái cũ nhất khi đầy
+#[derive(Debug, Default)]
+struct FinalizedOrders {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl FinalizedOrders {
+    fn insert(&mut self, req_id: String) {
+        if !self.ids.insert(req_id.clone()) {
+            return;
+        }
+        self.order.push_back(req_id);
+        if self.order.len() > FINALIZED_MEMORY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+    }
+
+    fn contains(&self, req_id: &str) -> bool {
+        self.ids.contains(req_id)
+    }
+}
+
+/// Một tài khoản Gate.io: credentials và signer, lệnh đang chờ phản hồi, các sink
+/// output và [`LatencyStats`] của cả run. Dùng chung (qua `Arc`) cho mọi connection
+#[derive(Debug)]
+pub struct GateIOAccount {
+    api_key: String,
+    // Theo --key-type, key được parse 1 lần lúc khởi tạo
+    signer: Box<dyn Signer>,
+    account_name: String,
+    args: Arc<Args>,
+    sent_time_map: Arc<Mutex<HashMap<String, PendingOrder>>>,
+    response_count: Arc<Mutex<HashMap<String, u32>>>,
+    response_times: Arc<Mutex<HashMap<String, HashMap<String, f64>>>>,
+    latency_stats: Arc<Mutex<LatencyStats>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    // --max-inflight: permit giữ từ lúc gửi tới khi lệnh được finalize (hoặc expire)
+    inflight: Option<Arc<tokio::sync::Semaphore>>,
+    inflight_permits: Arc<Mutex<HashMap<String, tokio::sync::OwnedSemaphorePermit>>>,
+    breaker: Option<Arc<CircuitBreaker>>,
+    backpressure: Arc<Backpressure>,
+    sinks: Arc<Mutex<Vec<Box<dyn LatencySink>>>>,
+    order_seq: Arc<AtomicU64>,
+    finalized_orders: Arc<Mutex<FinalizedOrders>>,
+    // false khi đang drain lúc shutdown: không nhận lệnh mới
+    accepting_orders: Arc<AtomicBool>,
+    // Client text -> req_id, để khớp phản hồi không có request_id trong header
+    text_index: Arc<Mutex<HashMap<String, String>>>,
+    // Tick giá/khối lượng theo cặp, từ validate_instruments
+    precisions: Arc<Mutex<HashMap<String, Precision>>>,
+    // Mọi giá trị ngẫu nhiên của run lấy từ RNG này để --seed tái lập được
+    seed: u64,
+    rng: Arc<Mutex<StdRng>>,
+    // Số cặp đã xong fire phase (đã gửi hết lệnh hoặc bỏ qua), cho --exit-after-order
+    fire_phases_done: Arc<AtomicUsize>,
+    decision_seq: Arc<AtomicU64>,
+    // Đánh thức --chain mỗi khi có lệnh được finalize
+    order_done: Arc<tokio::sync::Notify>,
+    // UID từ login, cần cho payload channel private của futures
+    uid: Arc<Mutex<Option<String>>>,
+    // --audit: payload lệnh đúng như gửi lên wire
+    audit: Option<Mutex<BufWriter<File>>>,
+    trace: Option<Mutex<OrderTrace>>,
+    ops: Arc<Mutex<OpsStats>>,
+    // Lệch giờ server - local (giây), cập nhật khi login lỗi timestamp
+    clock_offset: Arc<AtomicI64>,
+    auth_attempts: Arc<AtomicU32>,
+}
+
+impl GateIOAccount {
+    /// Tạo account từ API key/secret; `args` quyết định thuật toán ký, sink output,
+    /// rate limiter và các guard. Lỗi khi secret không hợp lệ hoặc không mở được file output
+    pub fn new(api_key: String, api_secret: String, account_name: String, args: Arc<Args>) -> Result<Self> {
+        let sinks = Arc::new(Mutex::new(build_sinks(&args, &account_name)?));
+        let seed = args.seed.unwrap_or_else(rand::random);
+
+        let signer = build_signer(args.key_type, args.hmac_digest, &api_secret)?;
+        let audit = match &args.audit {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+                Some(Mutex::new(BufWriter::new(file)))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            api_key,
+            signer,
+            account_name,
+            sent_time_map: Arc::new(Mutex::new(HashMap::new())),
+            response_count: Arc::new(Mutex::new(HashMap::new())),
+            response_times: Arc::new(Mutex::new(HashMap::new())),
+            latency_stats: Arc::new(Mutex::new(LatencyStats::default())),
+            rate_limiter: match (args.rate, args.ramp) {
+                (None, None) => None,
+                (rate, ramp) => Some(Arc::new(RateLimiter::new(rate.unwrap_or(0.0), ramp))),
+            },
+            inflight: args.max_inflight.map(|n| Arc::new(tokio::sync::Semaphore::new(n as usize))),
+            inflight_permits: Arc::new(Mutex::new(HashMap::new())),
+            breaker: args
+                .breaker_threshold
+                .map(|k| Arc::new(CircuitBreaker::new(k, Duration::from_secs(args.breaker_cooldown)))),
+            backpressure: Arc::new(Backpressure::new(args.backpressure_pause)),
+            sinks,
+            order_seq: Arc::new(AtomicU64::new(0)),
+            finalized_orders: Arc::new(Mutex::new(FinalizedOrders::default())),
+            accepting_orders: Arc::new(AtomicBool::new(true)),
+            text_index: Arc::new(Mutex::new(HashMap::new())),
+            precisions: Arc::new(Mutex::new(HashMap::new())),
+            seed,
+            rng: Arc::new(Mutex::new(StdRng::seed_from_u64(seed))),
+            fire_phases_done: Arc::new(AtomicUsize::new(0)),
+            decision_seq: Arc::new(AtomicU64::new(0)),
+            uid: Arc::new(Mutex::new(None)),
+            order_done: Arc::new(tokio::sync::Notify::new()),
+            audit,
+            trace: args.trace_order.then(|| Mutex::new(OrderTrace::default())),
+            ops: Arc::new(Mutex::new(OpsStats::new())),
+            clock_offset: Arc::new(AtomicI64::new(0)),
+            auth_attempts: Arc::new(AtomicU32::new(0)),
+            args,
+        })
+    }
+
+    fn stop_new_orders(&self) {
+        self.accepting_orders.store(false, Ordering::Relaxed);
+    }
+
+    /// Số lệnh (kể cả amend) đã gửi nhưng chưa có phản hồi cuối
+    pub fn pending_count(&self) -> usize {
+        self.sent_time_map.lock().unwrap().len()
+    }
+
+    /// Bản chụp [`LatencyStats`] tại thời điểm gọi
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency_stats.lock().unwrap().clone()
+    }
+
+    /// Ký và gửi một lệnh trên `ws_sender` (socket đã login, hoặc bất kỳ sink nào khi
+    /// test), bấm giờ ngay trước khi gửi. Phản hồi được khớp khi đưa frame vào
+    /// [`ConnectionManager::handle_frame`] của connection `conn`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn place_order<S>(
+        &self,
+        ws_sender: &mut S,
+        conn: usize,
+        side: &str,
+        symbol: &str,
+        quantity: f64,
+        price: f64,
+        order_type: &str,
+        time_in_force: &str,
+    ) -> Result<(), Error>
+    where
+        S: futures_util::Sink<Message, Error = tungstenite::Error> + Unpin,
+    {
+        let rate = self.throttle().await;
+        self.create_order(ws_sender, conn, side, symbol, quantity, price, order_type, time_in_force, None, None, rate, None).await
+    }
+
+    /// Permit của --max-inflight; log khi phải chờ
+    async fn acquire_inflight(&self, conn: usize) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let semaphore = self.inflight.clone()?;
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        say!(
+            "⏸ [{}#{}] --max-inflight {} reached, waiting for an order to finish",
+            self.account_name,
+            conn,
+            self.args.max_inflight.unwrap_or_default()
+        );
+        semaphore.acquire_owned().await.ok()
+    }
+
+    fn pending_count_on(&self, conn: usize) -> usize {
+        self.sent_time_map.lock().unwrap().values().filter(|p| p.conn == conn).count()
+    }
+
+    /// Bỏ các lệnh đang chờ thoả `expired` khỏi bookkeeping và tính là "no response",
+    /// phản hồi tới muộn sau đó được log như late response
+    fn expire_pending(&self, reason: &'static str, expired: impl Fn(&str, &PendingOrder) -> bool) -> usize {
+        let mut sent_time_map = self.sent_time_map.lock().unwrap();
+        let req_ids: Vec<String> = sent_time_map.iter().filter(|(id, p)| expired(id, p)).map(|(id, _)| id.clone()).collect();
+        if req_ids.is_empty() {
+            return 0;
+        }
+        let mut response_count = self.response_count.lock().unwrap();
+        let mut response_times = self.response_times.lock().unwrap();
+        let mut finalized_orders = self.finalized_orders.lock().unwrap();
+        for req_id in &req_ids {
+            let Some(pending) = sent_time_map.remove(req_id) else {
+                continue;
+            };
+            say!(
+                "⌛ [{}#{}] No final response for req_id {} after {:.1}s ({}), counting as lost",
+                self.account_name,
+                pending.conn,
+                req_id,
+                pending.sent_time.elapsed().as_secs_f64(),
+                reason
+            );
+            self.latency_stats.lock().unwrap().record_lost(&pending, reason);
+            self.record_outcome(Some(GateError::Transport));
+            response_count.remove(req_id);
+            response_times.remove(req_id);
+            self.inflight_permits.lock().unwrap().remove(req_id);
+            self.text_index.lock().unwrap().remove(&self.client_text(req_id));
+            finalized_orders.insert(req_id.clone());
+        }
+        self.order_done.notify_waiters();
+        req_ids.len()
+    }
+
+    /// --order-deadline: lệnh chưa nhận được phản hồi nào sau `deadline`
+    fn expire_unacked(&self, deadline: Duration) -> usize {
+        let unacked: HashSet<String> = self
+            .response_count
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| id.clone())
+            .collect();
+        if unacked.is_empty() {
+            return 0;
+        }
+        self.expire_pending(DEADLINE_REASON, |id, p| unacked.contains(id) && p.sent_time.elapsed() >= deadline)
+    }
+
+    fn record_response(&self, record: &OrderResponse) {
+        for sink in self.sinks.lock().unwrap().iter_mut() {
+            if let Err(e) = sink.record(record) {
+                error!("Failed to write latency record to {} sink: {}", sink.name(), e);
+            }
+        }
+    }
+
+    fn flush_outputs(&self) {
+        for sink in self.sinks.lock().unwrap().iter_mut() {
+            if let Err(e) = sink.flush() {
+                error!("Failed to flush {} sink: {}", sink.name(), e);
+            }
+        }
+        if let Some(audit) = &self.audit {
+            if let Err(e) = audit.lock().unwrap().flush() {
+                error!("Failed to flush audit log: {}", e);
+            }
+        }
+    }
+
+    /// Ghi một dòng JSONL vào --audit, payload nhúng nguyên văn chuỗi JSON đã gửi
+    fn audit_request(&self, req_id: &str, conn: usize, payload: &str) {
+        let Some(audit) = &self.audit else { return };
+        let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string();
+        let line = format!(
+            "{{\"time\":{},\"account\":{},\"conn\":{},\"req_id\":{},\"payload\":{}}}",
+            Value::from(time),
+            Value::from(self.account_name.as_str()),
+            conn,
+            Value::from(req_id),
+            payload
+        );
+        if let Err(e) = writeln!(audit.lock().unwrap(), "{}", line) {
+            error!("Failed to write audit log: {}", e);
+        }
+    }
+
+    /// Timestamp dùng để ký, đã cộng clock offset từ lần login lỗi timestamp
+    fn get_ts(&self) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        now.saturating_add_signed(self.clock_offset.load(Ordering::Relaxed))
+    }
+
+    fn get_ts_ms(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+
+    /// Thời gian local (ms) quy về đồng hồ server theo clock offset
+    fn server_ts_ms(&self) -> i64 {
+        self.get_ts_ms() as i64 + self.clock_offset.load(Ordering::Relaxed) * 1000
+    }
+
+    /// Tuổi (ms) của quote có timestamp server `t`, cùng theo đồng hồ server nên
+    /// không bị lệch đồng hồ local ảnh hưởng
+    fn quote_age_ms(&self, server_time_ms: u64) -> i64 {
+        self.server_ts_ms() - server_time_ms as i64
+    }
+
+    fn rest_auth(&self, method: &str, path: &str, query: &str, body: &str) -> RestAuth {
+        let ts = self.get_ts();
+        let sign = self.signer.sign_rest(method, path, query, body, ts);
+        if self.args.debug_signing {
+            say!(
+                "🔏 [{}] REST sign string {:?} -> {}...",
+                self.account_name,
+                rest_sign_string(method, path, query, body, ts),
+                truncate_str(&sign, 16)
+            );
+        }
+        RestAuth { key: self.api_key.clone(), sign, timestamp: ts.to_string() }
+    }
+
+    /// --rest-check: GET số dư có ký, kể cả query string, để key sai báo lỗi trước khi connect
+    async fn check_rest_credentials(&self) -> Result<()> {
+        let path = self.args.market.accounts_path();
+        let query = match self.args.market {
+            Market::Spot => self.args.pairs().first().and_then(|p| p.split_once('_')).map(|(_, quote)| format!("currency={}", quote)),
+            Market::Futures => None,
+        }
+        .unwrap_or_default();
+        let url = match query.as_str() {
+            "" => format!("{}{}", REST_BASE, path),
+            q => format!("{}{}?{}", REST_BASE, path, q),
+        };
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let response = self.rest_auth("GET", path, &query, "").apply(client.get(&url)).send().await?;
+        let status = response.status();
+        if status.is_success() {
+            say!("✅ [{}] REST credentials accepted ({} {})", self.account_name, path, status);
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(Error::Auth(format!("REST {} rejected the API key ({}): {}", path, status, truncate_str(&body, 200))).into());
+        }
+        say!("⚠️ [{}] REST credential check inconclusive: HTTP {} {}", self.account_name, status, truncate_str(&body, 200));
+        Ok(())
+    }
+
+    /// Ở chế độ per-message, mỗi request tự mang chữ ký nên bỏ qua bước ký
+    /// ở chế độ login
+    fn sign_request<T: Serialize>(&self, channel: &str, req_param: &T, ts: u64) -> Result<Option<RequestAuth>, Error> {
+        if self.args.auth_mode != AuthMode::PerMessage {
+            return Ok(None);
+        }
+        let req_param = serde_json::to_string(req_param)?;
+        let signature = self.signer.sign(channel, &req_param, ts);
+        self.log_signing(channel, &req_param, ts, &signature);
+        Ok(Some(RequestAuth {
+            api_key: self.api_key.clone(),
+            signature,
+            timestamp: ts.to_string(),
+        }))
+    }
+
+    /// Chỉ in khi --debug-signing; ở per-message, sign string chứa chi tiết lệnh
+    fn log_signing(&self, channel: &str, request_param: &str, ts: u64, signature: &str) {
+        if !self.args.debug_signing {
+            return;
+        }
+        say!("   🔧 Sign string: {:?}", sign_string(channel, request_param, ts));
+        say!("   ✍️ Signature ({}): {}...", self.signer.name(), truncate_str(signature, 20));
+    }
+
+    /// Subscribe các channel của --subscribe trên `ws_sender`; mỗi request tự mang chữ ký
+    async fn subscribe_private(&self, ws_sender: &mut WsSink, conn: usize) -> Result<(), Error> {
+        let uid = self.uid.lock().unwrap().clone();
+        for name in &self.args.subscribe {
+            let channel = self.args.market.private_channel(name)?;
+            let payload = match (self.args.market, channel.ends_with(".balances")) {
+                (Market::Spot, true) => Vec::new(),
+                (Market::Spot, false) => self.args.pairs(),
+                (Market::Futures, balances) => {
+                    let Some(uid) = uid.clone() else {
+                        say!("⚠️ [{}#{}] Not subscribing {}: futures channels need the UID from login", self.account_name, conn, channel);
+                        continue;
+                    };
+                    if balances { vec![uid] } else { vec![uid, "!all".to_string()] }
+                }
+            };
+            let time = self.get_ts();
+            let sign = self.signer.sign_message(&format!("channel={}&event=subscribe&time={}", channel, time));
+            let request = PrivateSubscribe {
+                time,
+                channel: channel.clone(),
+                event: "subscribe".to_string(),
+                payload,
+                auth: ChannelAuth { method: "api_key", key: self.api_key.clone(), sign },
+            };
+            ws_sender.send(Message::Text(serde_json::to_string(&request)?)).await?;
+            say!("📡 [{}#{}] Subscribing to {}", self.account_name, conn, channel);
+        }
+        Ok(())
+    }
+
+    /// Log ack subscribe và các update của channel private
+    fn handle_private_update(&self, channel: &str, event: &str, response: &Value) {
+        if event == "subscribe" {
+            match response.get("error").filter(|e| !e.is_null()) {
+                Some(error) => say!("❌ [{}] Subscribe {} failed: {}", self.account_name, channel, error),
+                None => say!("✅ [{}] Subscribed to {}", self.account_name, channel),
+            }
+            return;
+        }
+        if event != "update" {
+            return;
+        }
+        let field = |item: &Value, key: &str| match item.get(key) {
+            Some(Value::String(v)) => v.clone(),
+            Some(Value::Null) | None => "-".to_string(),
+            Some(v) => v.to_string(),
+        };
+        let items = match response_result(response) {
+            Some(Value::Array(items)) => items.clone(),
+            Some(item) => vec![item.clone()],
+            None => Vec::new(),
+        };
+        for item in &items {
+            if channel.ends_with(".orders") {
+                say!(
+                    "📦 [{}] Order update: id={} text={} event={} status={} price={} left={}",
+                    self.account_name,
+                    field(item, "id"),
+                    field(item, "text"),
+                    field(item, "event"),
+                    field(item, "status"),
+                    field(item, "price"),
+                    field(item, "left")
+                );
+            } else if channel.ends_with(".balances") {
+                say!(
+                    "💰 [{}] Balance update: {} available={} change={}",
+                    self.account_name,
+                    field(item, "currency"),
+                    field(item, "available"),
+                    field(item, "change")
+                );
+            } else {
+                say!(
+                    "🤝 [{}] User trade: id={} order_id={} price={} amount={}",
+                    self.account_name,
+                    field(item, "id"),
+                    field(item, "order_id"),
+                    field(item, "price"),
+                    field(item, "amount")
+                );
+            }
+        }
+    }
+
+    /// Giá và khối lượng dạng chuỗi theo tick của cặp; hướng làm tròn theo --round-mode,
+    /// mặc định BUY làm tròn giá xuống, SELL làm tròn lên, khối lượng luôn xuống
+    fn format_order_values(&self, symbol: &str, side: Side, price: f64, quantity: f64) -> (String, String) {
+        let precision = self.precisions.lock().unwrap().get(symbol).copied().unwrap_or_default();
+        let (price_mode, amount_mode) = match self.args.round_mode {
+            Some(mode) => (mode, mode),
+            None if side == Side::Buy => (RoundMode::Down, RoundMode::Down),
+            None => (RoundMode::Up, RoundMode::Down),
+        };
+        let price_str = precision.price.map_or_else(|| format_decimal(price), |step| step.format(price, price_mode));
+        let amount_str = precision.amount.map_or_else(|| format_decimal(quantity), |step| step.format(quantity, amount_mode));
+        if price_str.parse::<f64>().ok() != Some(price) {
+            say!("📐 [{}] Rounded {} {} price {} -> {} ({:?})", self.account_name, side, symbol, price, price_str, price_mode);
+        }
+        (price_str, amount_str)
+    }
+
+    /// Gate.io yêu cầu `text` bắt đầu bằng "t-" và tối đa 30 ký tự; server echo lại
+    /// trong ack, channel order update và REST nên dùng để đối chiếu lệnh
+    fn client_text(&self, req_id: &str) -> String {
+        let text = match &self.args.tag {
+            Some(tag) => format!("t-{}-{}", tag, req_id),
+            None => format!("t-{}", req_id),
+        };
+        truncate_str(&text, 30).to_string()
+    }
+
+    async fn authenticate(&self, conn: &ConnectionManager, ws_sender: &mut WsSink) -> Result<(), Error> {
+        let timestamp = self.get_ts();
+        let req_id = format!("auth-{}", self.get_ts_ms());
+        let request_param = "";
+
+        // Log bên dưới in 10 ký tự đầu/cuối của key
+        if self.api_key.len() < 20 {
+            return Err(Error::Auth("API key is missing or truncated".to_string()));
+        }
+
+        if self.args.auth_mode == AuthMode::PerMessage {
+            say!("🔐 [{}] Per-message auth: skipping login, each order is signed individually", self.account_name);
+            conn.set_authenticated(true);
+            conn.emit(ConnState::Authenticated);
+            return Ok(());
+        }
+        
+        say!("🔐 [{}] Starting authentication...", self.account_name);
+        say!("   📋 API Key: {}...{}", &self.api_key[..10], &self.api_key[self.api_key.len()-10..]);
+        say!("   🕒 Timestamp: {}", timestamp);
+        say!("   🆔 Request ID: {}", req_id);
+        
+        let login_channel = self.args.market.channel("login");
+        let signature = self.signer.sign(&login_channel, request_param, timestamp);
+        self.log_signing(&login_channel, request_param, timestamp, &signature);
+        
+        let auth_request = AuthRequest {
+            time: timestamp,
+            channel: login_channel,
+            event: "api".to_string(),
+            payload: AuthPayload {
+                api_key: self.api_key.clone(),
+                signature,
+                timestamp: timestamp.to_string(),
+                req_id,
+            },
+        };
+        
+        let auth_json = serde_json::to_string(&auth_request)?;
+        say!("   📦 Auth payload: {}...", truncate_str(&auth_json, 150));
+        
+        ws_sender.send(Message::Text(auth_json)).await?;
+        say!("   📤 Authentication request sent");
+        
+        Ok(())
+    }
+
+    /// Login lỗi timestamp: lấy giờ server từ response để chỉnh offset rồi
+    /// ký lại, tối đa --auth-retries lần
+    fn retry_auth(&self, header: Option<&serde_json::Map<String, Value>>, response: &Value) -> Option<FollowUp> {
+        let local_ms = self.get_ts_ms() as i64;
+        let server_ms = header
+            .and_then(|h| h.get("response_time"))
+            .and_then(|t| t.as_i64().or_else(|| t.as_str()?.parse().ok()))
+            .or_else(|| response.get("time_ms").and_then(|t| t.as_i64()))
+            .or_else(|| response.get("time").and_then(|t| t.as_i64()).map(|t| t * 1000));
+        if let Some(server_ms) = server_ms {
+            let offset = (server_ms - local_ms).div_euclid(1000);
+            self.clock_offset.store(offset, Ordering::Relaxed);
+            say!("🕰 [{}] Clock offset vs server: {} ms, signing with {:+}s", self.account_name, server_ms - local_ms, offset);
+        }
+
+        let attempt = self.auth_attempts.fetch_add(1, Ordering::Relaxed) + 1;
+        if attempt > self.args.auth_retries {
+            say!("❌ [{}] Timestamp auth error persists after {} retries, giving up", self.account_name, self.args.auth_retries);
+            return None;
+        }
+        say!("🔁 [{}] Timestamp auth error, retrying login ({}/{})", self.account_name, attempt, self.args.auth_retries);
+        Some(FollowUp::RetryAuth)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn create_order<S>(
+        &self,
+        ws_sender: &mut S,
+        conn: usize,
+        side: &str,
+        symbol: &str,
+        quantity: f64,
+        price: f64,
+        order_type: &str,
+        time_in_force: &str,
+        decision: Option<u64>,
+        step: Option<usize>,
+        rate: Option<f64>,
+        permit: Option<tokio::sync::OwnedSemaphorePermit>,
+    ) -> Result<(), Error>
+    where
+        S: futures_util::Sink<Message, Error = tungstenite::Error> + Unpin,
+    {
+        if !self.accepting_orders.load(Ordering::Relaxed) {
+            return Err(Error::Order("shutting down, not accepting new orders".to_string()));
+        }
+
+        // Validate trước khi build OrderParam để lỗi rõ ràng thay vì 400 từ server
+        let side: Side = side.parse()?;
+        let order_type: OrderType = order_type.parse()?;
+        let time_in_force: TimeInForce = time_in_force.parse()?;
+
+        if quantity <= 0.0 || price <= 0.0 {
+            return Err(Error::Order(format!("invalid amount {} / price {}", quantity, price)));
+        }
+
+        // Chặn giá bất thường từ feed (glitch) trước khi gửi
+        let below_min = self.args.min_price.is_some_and(|min| price < min);
+        let above_max = self.args.max_price.is_some_and(|max| price > max);
+        if below_min || above_max {
+            say!(
+                "🛑 [{}] Price guard rejected order @ {} (band: {} .. {})",
+                self.account_name,
+                price,
+                self.args.min_price.map_or("-".to_string(), |p| p.to_string()),
+                self.args.max_price.map_or("-".to_string(), |p| p.to_string()),
+            );
+            return Err(Error::Order(format!("Price {} outside configured band", price)));
+        }
+
+        // Chặn lỗi "order too small" ngay tại local, --no-min-check để test edge case
+        if !self.args.no_min_check {
+            let precision = self.precisions.lock().unwrap().get(symbol).copied().unwrap_or_default();
+            if let Some(shortfall) = precision.check_minimums(side, price, quantity) {
+                say!("🛑 [{}] Minimum check rejected {} order: {}", self.account_name, symbol, shortfall);
+                return Err(Error::Order(shortfall));
+            }
+        }
+
+        if let Some(breaker) = &self.breaker {
+            breaker.allow()?;
+        }
+
+        let ts = self.get_ts();
+        // Thêm số thứ tự để req_id không trùng khi nhiều lệnh gửi trong cùng 1 ms
+        let seq = self.order_seq.fetch_add(1, Ordering::Relaxed);
+        let req_id = format!("{}-{}", self.get_ts_ms(), seq);
+        let text = self.client_text(&req_id);
+        if self.trace_claim(&req_id) {
+            self.trace_mark(&req_id, "create_order (params validated)", Timestamp::now());
+        }
+
+        let (price_str, amount_str) = self.format_order_values(symbol, side, price, quantity);
+        let numeric = self.args.json_numbers;
+        let (price_str, amount_str) = (OrderNumber::new(price_str, numeric), OrderNumber::new(amount_str, numeric));
+        let order_param = match self.args.market {
+            Market::Spot => OrderParam::Spot(SpotOrderParam {
+                currency_pair: symbol.to_string(),
+                side,
+                order_type,
+                amount: amount_str,
+                price: price_str,
+                time_in_force,
+                text: Some(text.clone()),
+                account: self.args.account_mode.spot_account(),
+            }),
+            Market::Futures => {
+                if quantity.fract() != 0.0 {
+                    return Err(Error::Order(format!("Futures size must be a whole number of contracts, got {}", quantity)));
+                }
+                let size = quantity as i64;
+                let (price, tif) = match order_type {
+                    OrderType::Limit => (price_str, time_in_force),
+                    OrderType::Market => (OrderNumber::new("0".to_string(), numeric), TimeInForce::Ioc),
+                };
+                OrderParam::Futures(FuturesOrderParam {
+                    contract: symbol.to_string(),
+                    size: if side == Side::Sell { -size } else { size },
+                    price,
+                    tif,
+                    text: Some(text.clone()),
+                })
+            }
+        };
+
+        let now: DateTime<Utc> = Utc::now();
+        end_quiet_phase();
+        say!("\n🚀 [{}#{}] Placing order: {} {} {} @ {}", 
+            self.account_name, conn, side, quantity, symbol, price);
+        say!("🕒 Order sent at: {}", now.format("%H:%M:%S%.6f"));
+        say!("🏷 Client order id: {} (req_id {})", text, req_id);
+        say!("⏱ Starting latency measurement...");
+
+        // Ở chế độ per-message, thời gian ký tính vào latency để so sánh công bằng với login
+        let signing_started = (self.args.auth_mode == AuthMode::PerMessage).then(Timestamp::now);
+        let channel = self.args.market.channel("order_place");
+        let auth = self.sign_request(&channel, &order_param, ts)?;
+        let order_request = OrderRequest {
+            time: ts,
+            channel,
+            event: "api".to_string(),
+            payload: OrderPayload {
+                req_id: req_id.clone(),
+                req_param: order_param,
+                auth,
+            },
+        };
+
+        let order_json = serde_json::to_string(&order_request)?;
+        self.trace_mark(&req_id, "signed + serialized", Timestamp::now());
+
+        // Lưu thời gian gửi lệnh ngay trước ws_sender.send
+        let sent = signing_started.unwrap_or_else(Timestamp::now);
+        self.trace_mark(&req_id, "send (latency start)", sent);
+        self.sent_time_map.lock().unwrap().insert(req_id.clone(), PendingOrder {
+            sent_time: sent.instant,
+            sent,
+            conn,
+            pair: symbol.to_string(),
+            side: Some(side),
+            rate,
+            decision,
+            step,
+        });
+        self.response_count.lock().unwrap().insert(req_id.clone(), 0);
+        self.response_times.lock().unwrap().insert(req_id.clone(), HashMap::new());
+        self.text_index.lock().unwrap().insert(text, req_id.clone());
+        if let Some(permit) = permit {
+            self.inflight_permits.lock().unwrap().insert(req_id.clone(), permit);
+        }
+
+        self.audit_request(&req_id, conn, &order_json);
+
+        // Gửi lệnh
+        if let Err(e) = ws_sender.send(Message::Text(order_json)).await {
+            self.record_outcome(Some(GateError::Transport));
+            return Err(e.into());
+        }
+        self.trace_mark(&req_id, "send flushed to socket", Timestamp::now());
+
+        Ok(())
+    }
+
+    /// Server đang báo quá tải thì chờ, sau đó lấy token của rate limiter; trả về rate
+    /// đang áp dụng. Gọi trước khi giữ ws_sender để không chặn các lệnh khác của connection
+    async fn throttle(&self) -> Option<f64> {
+        self.backpressure.wait().await;
+        match &self.rate_limiter {
+            Some(limiter) => Some(limiter.acquire().await),
+            None => None,
+        }
+    }
+
+    /// Mốc quyết định của --trace-order; chỉ giữ quyết định gần nhất trước lệnh được trace
+    fn trace_decision(&self, pair: &str, ask: f64, bid: Option<f64>) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+        let mut trace = trace.lock().unwrap();
+        if trace.req_id.is_none() && !trace.done {
+            trace.events.clear();
+            let bid = bid.map_or("-".to_string(), |b| b.to_string());
+            trace.mark(format!("decision ({} ask {} bid {})", pair, ask, bid), Timestamp::now());
+        }
+    }
+
+    /// Lệnh đầu tiên sau khi bật --trace-order được trace; trả true nếu là lệnh đó
+    fn trace_claim(&self, req_id: &str) -> bool {
+        let Some(trace) = &self.trace else {
+            return false;
+        };
+        let mut trace = trace.lock().unwrap();
+        if trace.done {
+            return false;
+        }
+        match &trace.req_id {
+            Some(id) => id == req_id,
+            None => {
+                trace.req_id = Some(req_id.to_string());
+                true
+            }
+        }
+    }
+
+    fn trace_mark(&self, req_id: &str, label: impl Into<String>, at: Timestamp) {
+        if let Some(trace) = &self.trace {
+            let mut trace = trace.lock().unwrap();
+            if !trace.done && trace.req_id.as_deref() == Some(req_id) {
+                trace.mark(label, at);
+            }
+        }
+    }
+
+    /// Phản hồi cuối của lệnh được trace: in chuỗi mốc và dừng trace
+    fn trace_finish(&self, req_id: &str) {
+        if let Some(trace) = &self.trace {
+            let mut trace = trace.lock().unwrap();
+            if !trace.done && trace.req_id.as_deref() == Some(req_id) {
+                trace.print();
+                trace.done = true;
+            }
+        }
+    }
+
+    fn record_outcome(&self, failure: Option<GateError>) {
+        if let Some(kind) = failure {
+            *self.ops.lock().unwrap().failures.entry(kind).or_default() += 1;
+        }
+        if let Some(breaker) = &self.breaker {
+            match failure {
+                Some(kind) => breaker.record_failure(kind),
+                None => breaker.record_success(),
+            }
+        }
+    }
+
+    async fn amend_order<S>(
+        &self,
+        ws_sender: &mut S,
+        conn: usize,
+        order_id: &str,
+        currency_pair: &str,
+        new_price: Option<f64>,
+        new_amount: Option<f64>,
+    ) -> Result<(), Error>
+    where
+        S: futures_util::Sink<Message, Error = tungstenite::Error> + Unpin,
+    {
+        if !self.accepting_orders.load(Ordering::Relaxed) {
+            return Err(Error::Order("shutting down, not accepting new amends".to_string()));
+        }
+        if new_price.is_none() && new_amount.is_none() {
+            return Err(Error::Order("Amend requires a new price or a new amount".to_string()));
+        }
+        // AmendParam dùng field của spot (currency_pair/amount)
+        if self.args.market != Market::Spot {
+            return Err(Error::Order("Order amend is only supported on the spot market".to_string()));
+        }
+
+        let seq = self.order_seq.fetch_add(1, Ordering::Relaxed);
+        let req_id = format!("amend-{}-{}", self.get_ts_ms(), seq);
+
+        let ts = self.get_ts();
+        let channel = self.args.market.channel("order_amend");
+        let req_param = AmendParam {
+            order_id: order_id.to_string(),
+            currency_pair: currency_pair.to_string(),
+            price: new_price.map(|p| OrderNumber::new(format_decimal(p), self.args.json_numbers)),
+            amount: new_amount.map(|a| OrderNumber::new(format_decimal(a), self.args.json_numbers)),
+        };
+        let auth = self.sign_request(&channel, &req_param, ts)?;
+        let amend_request = AmendRequest {
+            time: ts,
+            channel,
+            event: "api".to_string(),
+            payload: AmendPayload {
+                req_id: req_id.clone(),
+                req_param,
+                auth,
+            },
+        };
+
+        let sent = Timestamp::now();
+        self.sent_time_map.lock().unwrap().insert(req_id.clone(), PendingOrder {
+            sent_time: sent.instant,
+            sent,
+            conn,
+            pair: currency_pair.to_uppercase(),
+            side: None,
+            rate: None,
+            decision: None,
+            step: None,
+        });
+        self.response_count.lock().unwrap().insert(req_id.clone(), 0);
+
+        say!(
+            "\n✏️ [{}#{}] Amending order {}: price {:?}, amount {:?}",
+            self.account_name, conn, order_id, new_price, new_amount
+        );
+
+        let amend_json = serde_json::to_string(&amend_request)?;
+        self.audit_request(&req_id, conn, &amend_json);
+        ws_sender.send(Message::Text(amend_json)).await?;
+
+        Ok(())
+    }
+
+    /// `received` được lấy ngay khi `ws_receiver.next()` trả về, trước khi parse JSON
+    fn handle_message(&self, conn: &ConnectionManager, message: &str, received: Timestamp) -> Result<Option<FollowUp>, Error> {
+        let Some(response) = parse_frame(&self.account_name, message) else {
+            return Ok(None);
+        };
+        let parsed = Timestamp::now();
+        let received_time = received.instant;
+        let dp = self.args.dp();
+        let market = self.args.market;
+        let login_channel = market.channel("login");
+
+        let envelope = parse_envelope(&response);
+        // --single-connection: frame orderbook thuộc về orderbook task, không xử lý ở đây
+        if conn.relays_book()
+            && [market.channel(self.args.book_channel.name()), market.channel("order_book"), market.channel("trades")]
+                .iter()
+                .any(|c| c == envelope.channel)
+        {
+            return Ok(Some(FollowUp::RelayBook(response)));
+        }
+        let Envelope { header, channel, event, .. } = envelope;
+
+        // Debug: In message
+        if channel == login_channel && event == "api" {
+            say!("📨 [{}] Full auth message: {}", self.account_name, message);
+        } else {
+            let short_msg = truncate_str(message, 100);
+            say!("📨 [{}] Received {}/{}: {}...", self.account_name, channel, event, short_msg);
+        }
+
+        // Xử lý authentication
+        if channel == login_channel && event == "api" {
+            say!("🔐 [{}] Processing authentication response...", self.account_name);
+            
+            let status = envelope.status.unwrap_or("");
+
+            say!("   📊 Header: {:?}", header);
+            say!("   📊 Status found: {}", status);
+
+            if status == "200" {
+                let uid = envelope.result.and_then(|r| r.get("uid")).and_then(|u| match u {
+                    Value::String(uid) => Some(uid.clone()),
+                    Value::Number(uid) => Some(uid.to_string()),
+                    _ => None,
+                });
+
+                say!("✅ [{}] Auth successful: Status {}, UID {}", self.account_name, status, uid.as_deref().unwrap_or("unknown"));
+                self.auth_attempts.store(0, Ordering::Relaxed);
+                *self.uid.lock().unwrap() = uid;
+                conn.set_authenticated(true);
+                say!("🎯 [{}] AUTHENTICATED flag set to true", self.account_name);
+                conn.emit(ConnState::Authenticated);
+                // Channel private chỉ subscribe sau khi login thành công
+                if !self.args.subscribe.is_empty() {
+                    return Ok(Some(FollowUp::SubscribePrivate));
+                }
+            } else {
+                let error_msg = format!("Status: {}", status);
+                let error = envelope.error_message.unwrap_or(&error_msg);
+                say!("❌ [{}] Auth failed: {}", self.account_name, error);
+                self.ops.lock().unwrap().auth_failures += 1;
+
+                if GateError::classify_auth(status, envelope.error_label, error) == GateError::Timestamp {
+                    return Ok(self.retry_auth(header, &response));
+                }
+            }
+            return Ok(None);
+        }
+
+        // Channel private của --subscribe
+        if self.args.subscribe.iter().any(|c| market.private_channel(c).is_ok_and(|c| c == channel)) {
+            self.handle_private_update(channel, event, &response);
+            return Ok(None);
+        }
+
+        // Xử lý ping/pong response
+        if channel == market.channel("ping") || channel == market.channel("pong") {
+            let rtt_ms = conn.ping_sent.lock().unwrap().take().map(|sent| received_time.duration_since(sent).as_secs_f64() * 1000.0);
+            let mut ops = self.ops.lock().unwrap();
+            ops.pongs += 1;
+            match rtt_ms {
+                Some(rtt_ms) => {
+                    say!("📡 [{}] Pong received, RTT {:.dp$} ms", self.account_name, rtt_ms);
+                    ops.ping_rtt.push(rtt_ms);
+                }
+                None => say!("📡 [{}] Ping/Pong response received", self.account_name),
+            }
+            return Ok(None);
+        }
+
+        // Xử lý phản hồi amend
+        if channel == market.channel("order_amend") && event == "api" {
+            let req_id = envelope.request_id.unwrap_or("");
+
+            let mut sent_time_map = self.sent_time_map.lock().unwrap();
+            if let Some(pending) = sent_time_map.get(req_id).cloned() {
+                let latency_ms = received_time.duration_since(pending.sent_time).as_secs_f64() * 1000.0;
+                let status = envelope.status.unwrap_or("unknown");
+                let is_ack = envelope.ack;
+                let succeeded = status == "200" || status == "201";
+                self.backpressure.observe(&envelope, self.server_ts_ms());
+
+                let mut response_count = self.response_count.lock().unwrap();
+                let count = response_count.entry(req_id.to_string()).or_insert(0);
+                *count += 1;
+                // Latency amend tính tới ack thành công của chính request đó (theo req_id của từng amend)
+                if is_ack && succeeded {
+                    self.latency_stats.lock().unwrap().record_amend(latency_ms);
+                }
+
+                report!("\n✏️ Amend {} received:", if is_ack { "ack" } else { "result" });
+                report!("   ⏱ Latency từ lúc amend → phản hồi: {:.dp$} ms", latency_ms);
+                report!("   📊 Status: {}", status);
+
+                if !is_ack || !succeeded {
+                    sent_time_map.remove(req_id);
+                    response_count.remove(req_id);
+                    drop(response_count);
+                    drop(sent_time_map);
+                    // Amend kết thúc được tính vào breaker/failures như lệnh đặt
+                    if succeeded {
+                        self.record_outcome(None);
+                    } else {
+                        self.record_outcome(Some(GateError::classify(status, envelope.error_label)));
+                    }
+                }
+            }
+            return Ok(None);
+        }
+
+        // Xử lý phản hồi đặt lệnh
+        let mut follow_up = None;
+        if channel == market.channel("order_place") && event == "api" {
+            say!("📋 [{}] Processing order response...", self.account_name);
+            if !conn.is_authenticated() {
+                say!("⚠️ [{}] Order response arrived before auth success (out-of-order)", conn.label());
+            }
+
+            let header_req_id = envelope.request_id.unwrap_or("");
+            // Header không có request_id thì tìm theo client `text` được echo trong result
+            let text_req_id = if header_req_id.is_empty() {
+                envelope
+                    .result
+                    .and_then(|r| r.get("text"))
+                    .and_then(|t| t.as_str())
+                    .and_then(|text| self.text_index.lock().unwrap().get(text).cloned())
+            } else {
+                None
+            };
+            let req_id = text_req_id.as_deref().unwrap_or(header_req_id);
+
+            if req_id.is_empty() {
+                say!(
+                    "⚠️ [{}] Order response has no request_id or known client text, cannot match: {}",
+                    self.account_name,
+                    truncate_str(message, 200)
+                );
+            } else {
+                let mut sent_time_map = self.sent_time_map.lock().unwrap();
+                if let Some(pending) = sent_time_map.get(req_id).cloned() {
+                    let latency = received_time.duration_since(pending.sent_time);
+                    let latency_ms = latency.as_secs_f64() * 1000.0;
+
+                    // Đếm số lần phản hồi
+                    let mut response_count = self.response_count.lock().unwrap();
+                    let count = response_count.entry(req_id.to_string()).or_insert(0);
+                    *count += 1;
+                    let total_responses = *count;
+
+                    let status = envelope.status.unwrap_or("unknown");
+                    self.backpressure.observe(&envelope, self.server_ts_ms());
+
+                    // Phân loại theo nội dung thay vì thứ tự đến: ack (`"ack": true`) là
+                    // Response 1, kết quả đặt lệnh là Response 2
+                    let is_ack = envelope.ack;
+                    let response_num = if is_ack { 1 } else { 2 };
+                    let response_key = format!("response_{}", response_num);
+                    self.trace_mark(req_id, format!("Response {} frame received", response_num), received);
+                    self.trace_mark(
+                        req_id,
+                        format!("Response {} parsed ({}: {:.dp$} ms)", response_num, response_label(response_num), latency_ms),
+                        parsed,
+                    );
+
+                    // Lưu thời gian phản hồi
+                    let mut response_times = self.response_times.lock().unwrap();
+                    let times = response_times.entry(req_id.to_string()).or_default();
+                    if times.contains_key(&response_key) {
+                        say!(
+                            "⚠️ [{}] Duplicate {} for req_id {} (status {}), ignoring",
+                            self.account_name,
+                            if is_ack { "ack" } else { "result" },
+                            req_id,
+                            status
+                        );
+                        return Ok(None);
+                    }
+                    times.insert(response_key, latency_ms);
+                    self.latency_stats.lock().unwrap().record(&pending, response_num, latency_ms, message.len());
+                    let server_ms = server_processing_ms(header);
+                    let fill = Fill::from_result(envelope.result);
+                    self.record_response(&OrderResponse {
+                        req_id: req_id.to_string(),
+                        conn: pending.conn,
+                        pair: pending.pair.clone(),
+                        response_num,
+                        status: status.to_string(),
+                        latency_ms,
+                        server_ms,
+                        size_bytes: message.len(),
+                        rate: pending.rate,
+                        fill: fill.clone(),
+                        side: pending.side,
+                        sent: pending.sent,
+                        received,
+                    });
+
+                    let now: DateTime<Utc> = Utc::now();
+                    report!("\n📥 Response {} ({}) received:", response_num, response_label(response_num));
+                    report!("   🕒 Time: {}", now.format("%H:%M:%S%.6f"));
+                    report!("   ⏱ Latency từ lúc đặt lệnh → Response {}: {:.dp$} ms", response_num, latency_ms);
+                    if let Some(server_ms) = server_ms {
+                        report!(
+                            "   🏦 Server processing: {:.dp$} ms (network + client: {:.dp$} ms)",
+                            server_ms,
+                            latency_ms - server_ms
+                        );
+                    }
+                    report!("   📊 Status: {}", status);
+                    if let Some(fill) = &fill {
+                        let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+                        match fill.filled_amount() {
+                            Some(filled) if filled > 0.0 => report!(
+                                "   💹 Fill: price={} filled_total={} filled={} amount={} left={}",
+                                show(&fill.price),
+                                show(&fill.filled_total),
+                                filled,
+                                show(&fill.amount),
+                                show(&fill.left)
+                            ),
+                            _ => report!("   💤 Resting, no fill (amount={} left={})", show(&fill.amount), show(&fill.left)),
+                        }
+                    }
+
+                    // In thông tin chi tiết phản hồi
+                    let result = envelope.result;
+                    if status == "201" {
+                        report!("   ✅ Order success: {:?}", result);
+                    } else if status == "400" {
+                        let err_msg = envelope.error_message.unwrap_or("Unknown error");
+                        report!("   ❌ Order rejected: {}", err_msg);
+                    } else {
+                        report!("   📋 Response result: {:?}", result);
+                    }
+
+                    // Nếu là phản hồi kết quả hoặc có lỗi, in tổng kết
+                    if !is_ack || (status != "200" && status != "201") {
+                        report!("\n🏁 [{}#{}] Order processing completed!", self.account_name, pending.conn);
+                        report!("📊 LATENCY SUMMARY:");
+
+                        let times = response_times.get(req_id).unwrap();
+                        if let Some(&response_1) = times.get("response_1") {
+                            report!("   ⏱ Đặt lệnh → Response 1 (receipt): {:.dp$} ms", response_1);
+                        }
+
+                        if let Some(&response_2) = times.get("response_2") {
+                            report!("   ⏱ Đặt lệnh → Response 2 (result): {:.dp$} ms", response_2);
+                        }
+
+                        if let (Some(&r1), Some(&r2)) = (times.get("response_1"), times.get("response_2")) {
+                            let diff = r2 - r1;
+                            report!("   ⏱ Response 1 → Response 2: {:.dp$} ms", diff);
+                        }
+
+                        report!("   📈 Total responses received: {}", total_responses);
+                        self.trace_finish(req_id);
+
+                        if status == "200" || status == "201" {
+                            self.record_outcome(None);
+                        } else {
+                            self.record_outcome(Some(GateError::classify(status, envelope.error_label)));
+                        }
+
+                        // Amend lệnh vừa đặt nếu có cấu hình
+                        let wants_amend = self.args.amend_price.is_some() || self.args.amend_amount.is_some();
+                        if wants_amend && !is_ack && (status == "200" || status == "201") {
+                            let order = envelope.result;
+                            let order_id = order.and_then(|o| o.get("id")).and_then(|i| i.as_str());
+                            let currency_pair = order.and_then(|o| o.get("currency_pair")).and_then(|c| c.as_str());
+                            match (order_id, currency_pair) {
+                                (Some(order_id), Some(currency_pair)) => {
+                                    follow_up = Some(FollowUp::Amend {
+                                        order_id: order_id.to_string(),
+                                        currency_pair: currency_pair.to_string(),
+                                    });
+                                }
+                                _ => say!("⚠️ [{}] Cannot amend: order id not found in result", self.account_name),
+                            }
+                        }
+
+                        // Dọn dẹp
+                        sent_time_map.remove(req_id);
+                        response_count.remove(req_id);
+                        response_times.remove(req_id);
+                        self.inflight_permits.lock().unwrap().remove(req_id);
+                        self.text_index.lock().unwrap().remove(&self.client_text(req_id));
+                        self.finalized_orders.lock().unwrap().insert(req_id.to_string());
+                        self.order_done.notify_waiters();
+                    }
+                } else if self.finalized_orders.lock().unwrap().contains(req_id) {
+                    say!(
+                        "⚠️ [{}] Late response for already finalized req_id {}: {}",
+                        self.account_name,
+                        req_id,
+                        message
+                    );
+                } else {
+                    say!("⚠️ [{}] Response for unknown req_id {}, no pending order matches", self.account_name, req_id);
+                }
+            }
+        }
+
+        Ok(follow_up)
+    }
+}
+
+/// Trạng thái vòng đời của một trading connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConnState {
+    Connecting,
+    Connected,
+    Authenticated,
+    Disconnected,
+    Reconnecting,
+}
+
+/// Thời điểm task có tiến triển gần nhất (nhận/gửi message), cho --watchdog
+#[derive(Debug)]
+struct Progress {
+    started: Instant,
+    last_ms: AtomicU64,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self { started: Instant::now(), last_ms: AtomicU64::new(0) }
+    }
+
+    fn touch(&self) {
+        self.last_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle(&self) -> Duration {
+        self.started.elapsed().saturating_sub(Duration::from_millis(self.last_ms.load(Ordering::Relaxed)))
+    }
+}
+
+/// Drop JoinHandle không dừng task; guard này abort task khi supervisor bị huỷ
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ConnEvent {
+    conn: usize,
+    state: ConnState,
+    at: Instant,
+}
+
+/// Một trading WebSocket đã authenticate. Lệnh được gửi trên chính socket này
+/// thay vì mở connection mới chưa login.
+pub struct ConnectionManager {
+    index: usize,
+    account: Arc<GateIOAccount>,
+    ws_sender: tokio::sync::Mutex<Option<WsSink>>,
+    events: mpsc::UnboundedSender<ConnEvent>,
+    progress: Progress,
+    // Số lệnh đã gửi trên connection, cho --inject drop-every
+    orders_sent: AtomicU64,
+    // Auth là trạng thái của từng socket: reset khi connection rớt, login lại mới bật
+    authenticated: AtomicBool,
+    // Đánh thức các lệnh đang chờ auth (--queue-until-auth-ms)
+    auth_notify: tokio::sync::Notify,
+    // --single-connection: orderbook task đang nhận frame book của connection này
+    book_relay: Mutex<Option<mpsc::UnboundedSender<BookFrame>>>,
+    // Lúc gửi ping gần nhất chưa có pong, để đo RTT
+    ping_sent: Mutex<Option<Instant>>,
+}
+
+impl ConnectionManager {
+    /// Connection thứ `index` của `account`, chưa kết nối; không ai nhận event trạng thái
+    pub fn new(index: usize, account: Arc<GateIOAccount>) -> Self {
+        Self::with_events(index, account, mpsc::unbounded_channel().0)
+    }
+
+    fn with_events(index: usize, account: Arc<GateIOAccount>, events: mpsc::UnboundedSender<ConnEvent>) -> Self {
+        Self {
+            index,
+            account,
+            ws_sender: tokio::sync::Mutex::new(None),
+            events,
+            progress: Progress::new(),
+            orders_sent: AtomicU64::new(0),
+            authenticated: AtomicBool::new(false),
+            auth_notify: tokio::sync::Notify::new(),
+            book_relay: Mutex::new(None),
+            ping_sent: Mutex::new(None),
+        }
+    }
+
+    fn relays_book(&self) -> bool {
+        self.book_relay.lock().unwrap().is_some()
+    }
+
+    /// Chuyển frame book cho orderbook task; task đã dừng thì bỏ relay
+    fn relay_book(&self, frame: BookFrame) {
+        let mut relay = self.book_relay.lock().unwrap();
+        if relay.as_ref().is_some_and(|tx| tx.send(frame).is_err()) {
+            *relay = None;
+        }
+    }
+
+    /// Connection rớt: kết thúc stream book để orderbook task subscribe lại sau reconnect
+    fn mark_disconnected(&self) {
+        self.set_authenticated(false);
+        self.book_relay.lock().unwrap().take();
+        self.ping_sent.lock().unwrap().take();
+        self.emit(ConnState::Disconnected);
+    }
+
+    /// true khi login của socket hiện tại đã thành công; reset khi connection rớt
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Acquire)
+    }
+
+    /// Xử lý một text frame nhận trên connection này như trading loop: login, phản
+    /// hồi lệnh/amend, channel private. Request tiếp theo (amend, subscribe private,
+    /// login lại) không được gửi vì không có socket
+    pub fn handle_frame(&self, message: &str) -> Result<(), Error> {
+        self.account.handle_message(self, message, Timestamp::now()).map(drop)
+    }
+
+    fn set_authenticated(&self, authenticated: bool) {
+        self.authenticated.store(authenticated, Ordering::Release);
+        if authenticated {
+            self.auth_notify.notify_waiters();
+        }
+    }
+
+    /// Chờ tối đa `timeout` cho tới khi connection này authenticated, trả về trạng thái cuối
+    async fn wait_authenticated(&self, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                // Đăng ký trước khi kiểm tra cờ để không lỡ notify
+                let notified = self.auth_notify.notified();
+                if self.is_authenticated() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, wait).await;
+        self.is_authenticated()
+    }
+
+    fn emit(&self, state: ConnState) {
+        // Consumer có thể đã dừng; mất event không ảnh hưởng tới trading loop
+        let _ = self.events.send(ConnEvent {
+            conn: self.index,
+            state,
+            at: Instant::now(),
+        });
+    }
+
+    /// Ping ứng dụng (`spot.ping`/`futures.ping`) kèm timestamp; thời điểm gửi được
+    /// giữ lại để handle_message tính RTT khi pong về
+    async fn send_ping(&self) -> Result<(), Error> {
+        let ping = serde_json::json!({ "time": self.account.get_ts(), "channel": self.account.args.market.channel("ping") });
+        let mut guard = self.ws_sender.lock().await;
+        let ws_sender = guard
+            .as_mut()
+            .ok_or_else(|| Error::Connect(format!("Trading connection {} is not connected", self.label())))?;
+        *self.ping_sent.lock().unwrap() = Some(Instant::now());
+        ws_sender.send(Message::Text(ping.to_string())).await?;
+        Ok(())
+    }
+
+    /// Nhãn `account#index` dùng trong log
+    pub fn label(&self) -> String {
+        format!("{}#{}", self.account.account_name, self.index)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn create_order(
+        &self,
+        side: &str,
+        symbol: &str,
+        quantity: f64,
+        price: f64,
+        order_type: &str,
+        time_in_force: &str,
+        decision: Option<u64>,
+        step: Option<usize>,
+    ) -> Result<(), Error> {
+        if !self.is_authenticated() {
+            say!("⚠️ [{}] create_order called before this connection authenticated", self.label());
+            if let Some(ms) = self.account.args.queue_until_auth_ms {
+                if self.wait_authenticated(Duration::from_millis(ms)).await {
+                    say!("📬 [{}] Authenticated, sending queued order", self.label());
+                } else {
+                    say!("⌛ [{}] Queued order dropped: not authenticated within {} ms", self.label(), ms);
+                }
+            }
+            if !self.is_authenticated() {
+                say!("❌ [{}] Cannot place order - connection not authenticated", self.label());
+                return Err(Error::Auth(format!("connection {} not authenticated", self.label())));
+            }
+        }
+
+        if let Some(pct) = self.account.args.inject_reject_rate() {
+            if self.account.rng.lock().unwrap().gen_range(0.0..100.0) < pct {
+                say!("💉 [{}] Injected rejection ({}% reject-rate)", self.label(), pct);
+                self.account.record_outcome(Some(GateError::Rejected));
+                return Err(Error::Order("injected rejection".to_string()));
+            }
+        }
+
+        // Chờ permit và token rate limiter trước khi giữ sender để read loop (amend)
+        // và các lệnh khác trên connection không bị chặn
+        let permit = self.account.acquire_inflight(self.index).await;
+        let rate = self.account.throttle().await;
+        let mut guard = self.ws_sender.lock().await;
+        let ws_sender = guard
+            .as_mut()
+            .ok_or_else(|| Error::Connect(format!("Trading connection {} is not connected", self.label())))?;
+        self.account
+            .create_order(ws_sender, self.index, side, symbol, quantity, price, order_type, time_in_force, decision, step, rate, permit)
+            .await?;
+        self.progress.touch();
+
+        if let Some(n) = self.account.args.inject_drop_every() {
+            let sent = self.orders_sent.fetch_add(1, Ordering::Relaxed) + 1;
+            if sent.is_multiple_of(n) {
+                say!("💉 [{}] Injected disconnect after {} order(s)", self.label(), sent);
+                if let Err(e) = ws_sender.close().await {
+                    say!("⚠️ [{}] Injected close failed: {}", self.label(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Trước lệnh kế tiếp: --chain chờ mọi lệnh của connection được finalize rồi nghỉ
+    /// --chain-delay, còn không thì nghỉ theo --inter-order; ngoài thời gian đo latency
+    async fn pace_next_order(&self) {
+        let args = &self.account.args;
+        if args.chain {
+            loop {
+                // Notified tạo trước khi kiểm tra nên không lỡ notify xảy ra ở giữa
+                let done = self.account.order_done.notified();
+                if self.account.pending_count_on(self.index) == 0 {
+                    break;
+                }
+                done.await;
+            }
+            if let Some(delay) = args.chain_delay {
+                sleep(delay).await;
+            }
+        } else if let Some(dist) = args.inter_order {
+            let delay = dist.next_delay(&mut self.account.rng.lock().unwrap());
+            sleep(delay).await;
+        }
+    }
+
+    /// --duration: hết thời gian tính từ lệnh đầu thì không đặt thêm
+    fn duration_reached(&self, started: Instant) -> bool {
+        let reached = self.account.args.duration.is_some_and(|d| started.elapsed() >= d);
+        if reached {
+            say!("⏹ [{}] --duration reached, not placing further orders", self.label());
+        }
+        reached
+    }
+
+    async fn reauthenticate(&self) -> Result<(), Error> {
+        let mut guard = self.ws_sender.lock().await;
+        let ws_sender = guard
+            .as_mut()
+            .ok_or_else(|| Error::Connect(format!("Trading connection {} is not connected", self.label())))?;
+        self.account.authenticate(self, ws_sender).await
+    }
+
+    async fn subscribe_private(&self) -> Result<(), Error> {
+        let mut guard = self.ws_sender.lock().await;
+        let ws_sender = guard
+            .as_mut()
+            .ok_or_else(|| Error::Connect(format!("Trading connection {} is not connected", self.label())))?;
+        self.account.subscribe_private(ws_sender, self.index).await
+    }
+
+    async fn amend_order(&self, order_id: &str, currency_pair: &str, new_price: Option<f64>, new_amount: Option<f64>) -> Result<(), Error> {
+        let mut guard = self.ws_sender.lock().await;
+        let ws_sender = guard
+            .as_mut()
+            .ok_or_else(|| Error::Connect(format!("Trading connection {} is not connected", self.label())))?;
+        self.account
+            .amend_order(ws_sender, self.index, order_id, currency_pair, new_price, new_amount)
+            .await?;
+        self.progress.touch();
+        Ok(())
+    }
+}
+
+/// Subscribe/unsubscribe book_ticker (và order_book nếu có --depth-levels) cho `pairs`
+async fn send_book_subscription(ws_sender: &mut WsSink, args: &Args, pairs: &[String], event: &str) -> Result<()> {
+    let verb = if event == "subscribe" { "✅ Subscribed to" } else { "🔕 Unsubscribed from" };
+    let channel = args.market.channel(args.book_channel.name());
+    match args.book_channel {
+        BookChannel::BookTicker => {
+            let subscribe_msg = OrderbookSubscribe {
+                time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                channel: channel.clone(),
+                event: event.to_string(),
+                payload: pairs.to_vec(),
+            };
+            
+            let subscribe_json = serde_json::to_string(&subscribe_msg)?;
+            ws_sender.send(Message::Text(subscribe_json)).await?;
+        }
+        // order_book_update nhận 1 cặp + interval mỗi lần subscribe
+        BookChannel::OrderBookUpdate => {
+            for pair in pairs {
+                let subscribe_msg = OrderbookSubscribe {
+                    time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                    channel: channel.clone(),
+                    event: event.to_string(),
+                    payload: vec![pair.clone(), args.book_interval.clone()],
+                };
+                ws_sender.send(Message::Text(serde_json::to_string(&subscribe_msg)?)).await?;
+            }
+        }
+    }
+    say!("{} Gate.io {} for {}", verb, channel, pairs.join(", "));
+
+    // order_book chỉ nhận 1 cặp mỗi lần subscribe
+    if let Some(levels) = &args.depth_levels {
+        for pair in pairs {
+            let depth_msg = OrderbookSubscribe {
+                time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                channel: args.market.channel("order_book"),
+                event: event.to_string(),
+                payload: vec![pair.clone(), levels.clone(), "100ms".to_string()],
+            };
+            ws_sender.send(Message::Text(serde_json::to_string(&depth_msg)?)).await?;
+            say!("{} Gate.io depth ({} levels) for {}", verb, levels, pair);
+        }
+    }
+
+    if args.with_trades {
+        let trades_msg = OrderbookSubscribe {
+            time: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            channel: args.market.channel("trades"),
+            event: event.to_string(),
+            payload: pairs.to_vec(),
+        };
+        ws_sender.send(Message::Text(serde_json::to_string(&trades_msg)?)).await?;
+        say!("{} Gate.io trades for {}", verb, pairs.join(", "));
+    }
+    Ok(())
+}
+
+/// Đọc từng dòng stdin trên thread riêng (detached); channel đóng khi stdin EOF.
+/// tokio::io::stdin đọc blocking không hủy được nên drop runtime sẽ treo chờ nó
+/// khi stdin còn mở, thread detached thì không giữ process lại
+fn spawn_stdin_commands() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if !line.trim().is_empty() && tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Frame orderbook đã decode, kèm thời điểm nhận để đo receive -> SharePrice update
+struct BookFrame {
+    received: Instant,
+    message: Message,
+    data: Option<Value>,
+}
+
+fn decode_book_frame(message: Message, received: Instant) -> BookFrame {
+    let data = match &message {
+        Message::Text(text) => parse_frame("orderbook", text),
+        _ => None,
+    };
+    BookFrame { received, message, data }
+}
+
+type BookFrames = Pin<Box<dyn Stream<Item = (usize, Result<BookFrame, tungstenite::Error>)> + Send>>;
+
+/// Gộp các feed thành một stream frame đã decode; với --decode-workers việc parse
+/// JSON chạy trên N worker task cố định nhận frame qua channel, `buffered` giữ
+/// nguyên thứ tự frame. Worker chết (panic) thành item lỗi thay vì panic cả loop
+fn book_frames<S>(feeds: S, decode_workers: Option<u32>) -> BookFrames
+where
+    S: Stream<Item = (usize, Result<Message, tungstenite::Error>)> + Send + 'static,
+{
+    let Some(workers) = decode_workers else {
+        return Box::pin(feeds.map(|(feed, message)| {
+            let received = Instant::now();
+            (feed, message.map(|message| decode_book_frame(message, received)))
+        }));
+    };
+    let (jobs, queue) = mpsc::channel::<(Message, Instant, tokio::sync::oneshot::Sender<BookFrame>)>(workers as usize);
+    let queue = Arc::new(tokio::sync::Mutex::new(queue));
+    for _ in 0..workers {
+        let queue = queue.clone();
+        // Kết thúc khi stream bị drop (sender của channel đóng)
+        tokio::spawn(async move {
+            loop {
+                let job = queue.lock().await.recv().await;
+                let Some((message, received, reply)) = job else { break };
+                let _ = reply.send(decode_book_frame(message, received));
+            }
+        });
+    }
+    let worker_lost = || tungstenite::Error::Io(std::io::Error::other("orderbook decode worker stopped"));
+    Box::pin(
+        feeds
+            .map(move |(feed, message)| {
+                let jobs = jobs.clone();
+                async move {
+                    let received = Instant::now();
+                    let frame = match message {
+                        Ok(message) => {
+                            let (reply, frame) = tokio::sync::oneshot::channel();
+                            match jobs.send((message, received, reply)).await {
+                                Ok(()) => frame.await.map_err(|_| worker_lost()),
+                                Err(_) => Err(worker_lost()),
+                            }
+                        }
+                        Err(e) => Err(e),
+                    };
+                    (feed, frame)
+                }
+            })
+            .buffered(workers as usize),
+    )
+}
+
+/// Thời gian từ lúc nhận frame tới khi SharePrice được cập nhật
+#[derive(Debug, Default)]
+struct DecodeTiming {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl DecodeTiming {
+    const LOG_EVERY: u64 = 1000;
+
+    fn record(&mut self, elapsed: Duration, workers: Option<u32>) {
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+        if self.count.is_multiple_of(Self::LOG_EVERY) {
+            say!(
+                "⏱ Book receive -> price update: avg {:.1} µs, max {:.1} µs over {} updates ({})",
+                self.total.as_secs_f64() * 1e6 / self.count as f64,
+                self.max.as_secs_f64() * 1e6,
+                self.count,
+                workers.map_or("inline decode".to_string(), |n| format!("{} decode workers", n))
+            );
+        }
+    }
+}
+
+/// State của orderbook task được giữ qua các lần watchdog restart, để cặp đã
+/// đặt lệnh không bị fire lại (trừ khi --reorder-on-reconnect) và stdin chỉ có 1 reader
+struct BookState {
+    order_placed: Mutex<HashSet<String>>,
+    // Lệnh từ stdin, ví dụ `symbol ALCH_BTC` để đổi cặp mà không restart
+    commands: tokio::sync::Mutex<mpsc::UnboundedReceiver<String>>,
+    progress: Progress,
+}
+
+impl BookState {
+    /// Sau khi orderbook task restart: mặc định cặp đã đặt lệnh giữ nguyên (không fire
+    /// lại trong run), với `reorder` (--reorder-on-reconnect) mọi cặp được fire lại.
+    /// Trả về các cặp được mở lại, đã sort
+    fn after_restart(&self, reorder: bool) -> Vec<String> {
+        if !reorder {
+            return Vec::new();
+        }
+        let mut reopened: Vec<String> = self.order_placed.lock().unwrap().drain().collect();
+        reopened.sort();
+        reopened
+    }
+}
+
+/// Chạy orderbook task; với --watchdog task được restart khi kết thúc hoặc im lặng quá lâu
+async fn supervise_orderbook(
+    managers: Vec<Arc<ConnectionManager>>,
+    warm: watch::Receiver<bool>,
+    args: Arc<Args>,
+    book_log: Option<Arc<Mutex<BufWriter<File>>>>,
+) -> Result<()> {
+    let state = Arc::new(BookState {
+        order_placed: Mutex::new(HashSet::new()),
+        commands: tokio::sync::Mutex::new(spawn_stdin_commands()),
+        progress: Progress::new(),
+    });
+    loop {
+        state.progress.touch();
+        let mut task = AbortOnDrop(tokio::spawn(start_gateio_orderbook_ws(
+            managers.clone(),
+            warm.clone(),
+            args.clone(),
+            book_log.clone(),
+            state.clone(),
+        )));
+        let Some(limit) = args.watchdog.map(Duration::from_secs) else {
+            return (&mut task.0).await?;
+        };
+        let reason = loop {
+            tokio::select! {
+                result = &mut task.0 => break match result {
+                    Ok(Ok(())) => "ended".to_string(),
+                    Ok(Err(e)) if matches!(e.downcast_ref::<Error>(), Some(Error::Closed(_))) => return Err(e),
+                    Ok(Err(e)) => format!("failed: {}", e),
+                    Err(e) => format!("panicked: {}", e),
+                },
+                _ = sleep(Duration::from_secs(1)) => {
+                    if state.progress.idle() >= limit {
+                        task.0.abort();
+                        break format!("silent for {}s", limit.as_secs());
+                    }
+                }
+            }
+        };
+        say!("🐕 Watchdog: orderbook task {}, restarting", reason);
+        let reopened = state.after_restart(args.reorder_on_reconnect);
+        if !reopened.is_empty() {
+            say!("🔁 --reorder-on-reconnect: {} may place orders again after the restart", reopened.join(", "));
+        }
+        sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Chạy trading connection; với --watchdog task được restart khi panic, hoặc khi có
+/// lệnh đang chờ mà connection không có traffic quá lâu
+async fn supervise_trading(manager: Arc<ConnectionManager>) -> Result<()> {
+    loop {
+        manager.progress.touch();
+        let mut task = AbortOnDrop(tokio::spawn(start_trading_ws(manager.clone())));
+        let Some(limit) = manager.account.args.watchdog.map(Duration::from_secs) else {
+            return (&mut task.0).await?;
+        };
+        let reason = loop {
+            tokio::select! {
+                result = &mut task.0 => match result {
+                    // Lỗi fatal (auth, --max-reconnects) vẫn kết thúc run như trước
+                    Ok(result) => return result,
+                    Err(e) => break format!("panicked: {}", e),
+                },
+                _ = sleep(Duration::from_secs(1)) => {
+                    let pending = manager.account.pending_count_on(manager.index);
+                    if pending > 0 && manager.progress.idle() >= limit {
+                        task.0.abort();
+                        break format!("silent for {}s with {} pending order(s)", limit.as_secs(), pending);
+                    }
+                }
+            }
+        };
+        say!("🐕 Watchdog: trading task [{}] {}, restarting", manager.label(), reason);
+        // Task bị abort không chạy tới phần dọn dẹp cuối của start_trading_ws
+        *manager.ws_sender.lock().await = None;
+        manager.mark_disconnected();
+        manager.account.expire_pending("watchdog restart", |_, p| p.conn == manager.index);
+    }
+}
+
+/// Nơi orderbook task gửi subscribe: socket riêng, hoặc trading connection #0 (--single-connection)
+enum BookSenders {
+    Own(Vec<WsSink>),
+    Shared(Arc<ConnectionManager>),
+}
+
+impl BookSenders {
+    async fn send_subscription(&mut self, args: &Args, pairs: &[String], event: &str) -> Result<()> {
+        match self {
+            BookSenders::Own(ws_senders) => {
+                for ws_sender in ws_senders {
+                    send_book_subscription(ws_sender, args, pairs, event).await?;
+                }
+            }
+            BookSenders::Shared(manager) => {
+                let mut guard = manager.ws_sender.lock().await;
+                let ws_sender = guard.as_mut().ok_or_else(|| anyhow::anyhow!("[{}] not connected", manager.label()))?;
+                send_book_subscription(ws_sender, args, pairs, event).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// --single-connection: chờ trading connection #0 lên rồi nhận frame book qua relay.
+/// Stream kết thúc khi connection rớt; gọi lại sau reconnect để subscribe lại.
+async fn attach_book_relay(manager: &ConnectionManager) -> BookFrames {
+    while manager.ws_sender.lock().await.is_none() {
+        sleep(Duration::from_millis(50)).await;
+    }
+    let (tx, rx) = mpsc::unbounded_channel();
+    *manager.book_relay.lock().unwrap() = Some(tx);
+    Box::pin(futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|frame| ((0, Ok(frame)), rx)) }))
+}
+
+/// `warm` chuyển sang true khi mọi trading connection đã connect + authenticate
+/// (hết prepare phase); chỉ khi đó mới vào fire phase.
+async fn start_gateio_orderbook_ws(
+    managers: Vec<Arc<ConnectionManager>>,
+    warm: watch::Receiver<bool>,
+    args: Arc<Args>,
+    book_log: Option<Arc<Mutex<BufWriter<File>>>>,
+    state: Arc<BookState>,
+) -> Result<()> {
+    let mut pairs = args.pairs();
+    let ws_url = args.market.ws_url();
+    let book_channel = args.market.channel(args.book_channel.name());
+    let order_book_channel = args.market.channel("order_book");
+    let trades_channel = args.market.channel("trades");
+    
+    // --redundant-feed: 2 connection cùng endpoint, mọi message được gắn index của feed
+    let feeds = if args.redundant_feed { 2 } else { 1 };
+    let (mut ws_senders, mut ws_receiver) = if args.single_connection {
+        say!("📡 Single connection: orderbook shares [{}]", managers[0].label());
+        (BookSenders::Shared(managers[0].clone()), attach_book_relay(&managers[0]).await)
+    } else {
+        let mut ws_senders = Vec::new();
+        let mut receivers = Vec::new();
+        for feed in 0..feeds {
+            let ws_stream = connect_ws(Url::parse(ws_url)?, &args).await?;
+            if args.log_conn_info {
+                log_conn_info(&format!("orderbook#{}", feed), &ws_stream);
+            }
+            let (ws_sender, ws_receiver) = ws_stream.split();
+            ws_senders.push(ws_sender);
+            receivers.push(ws_receiver.map(move |message| (feed, message)));
+        }
+        (BookSenders::Own(ws_senders), book_frames(futures_util::stream::select_all(receivers), args.decode_workers))
+    };
+    let mut decode_timing = DecodeTiming::default();
+    let mut feed_seen = vec![Instant::now(); feeds];
+
+    say!("📡 Connecting to Gate.io orderbook for {}...", pairs.join(", "));
+    if feeds > 1 {
+        say!("📡 Redundant feed: {} orderbook connections, freshest update wins", feeds);
+    }
+    ws_senders.send_subscription(&args, &pairs, "subscribe").await?;
+
+    let mut commands = state.commands.lock().await;
+
+    // Giá theo từng cặp, mỗi cặp trigger và đặt lệnh độc lập
+    let share_prices: Arc<Mutex<HashMap<String, SharePrice>>> = Arc::new(Mutex::new(HashMap::new()));
+    let mut last_price_print: HashMap<String, Instant> = HashMap::new();
+    let mut tick_counts: HashMap<String, u64> = HashMap::new();
+    let mut book_prints: u64 = 0;
+    // Lần in book gần nhất của mọi cặp, cho --book-print-hz
+    let mut last_book_print: Option<Instant> = None;
+
+    loop {
+        let message = tokio::select! {
+            message = ws_receiver.next() => match message {
+                Some((feed, Ok(frame))) => {
+                    feed_seen[feed] = Instant::now();
+                    state.progress.touch();
+                    (feed, frame)
+                }
+                // Còn feed khác thì chạy tiếp trên feed đó
+                Some((feed, Err(e))) if feeds > 1 => {
+                    say!("⚠️ Orderbook feed #{} error: {}, continuing on the other feed", feed, e);
+                    continue;
+                }
+                Some((_, Err(e))) => return Err(e.into()),
+                // Trading connection #0 rớt: chờ reconnect rồi subscribe lại trên socket mới
+                None if args.single_connection => {
+                    say!("🔄 [{}] Orderbook relay lost, resubscribing after reconnect", managers[0].label());
+                    ws_receiver = attach_book_relay(&managers[0]).await;
+                    ws_senders.send_subscription(&args, &pairs, "subscribe").await?;
+                    continue;
+                }
+                None => break,
+            },
+            Some(line) = commands.recv() => {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some("symbol"), Some(new_pairs)) => {
+                        let new_pairs: Result<Vec<String>, Error> = new_pairs
+                            .split(',')
+                            .filter(|p| !p.trim().is_empty())
+                            .map(canonical_pair)
+                            .collect();
+                        let new_pairs = match new_pairs {
+                            Ok(new_pairs) if !new_pairs.is_empty() => new_pairs,
+                            Ok(_) => {
+                                say!("⚠️ Usage: symbol PAIR[,PAIR...]");
+                                continue;
+                            }
+                            Err(e) => {
+                                say!("⚠️ {}", e);
+                                continue;
+                            }
+                        };
+                        match validate_instruments(args.market, &new_pairs).await {
+                            Ok(precisions) => managers[0].account.precisions.lock().unwrap().extend(precisions),
+                            Err(e) => {
+                                say!("⚠️ Not switching: {}", e);
+                                continue;
+                            }
+                        }
+                        ws_senders.send_subscription(&args, &pairs, "unsubscribe").await?;
+                        // Bỏ giá cũ; update tới muộn của cặp cũ bị lọc vì không còn trong `pairs`
+                        {
+                            let mut share_prices = share_prices.lock().unwrap();
+                            for pair in pairs.iter().chain(new_pairs.iter()) {
+                                share_prices.remove(pair);
+                                tick_counts.remove(pair);
+                                last_price_print.remove(pair);
+                                state.order_placed.lock().unwrap().remove(pair);
+                            }
+                        }
+                        pairs = new_pairs;
+                        ws_senders.send_subscription(&args, &pairs, "subscribe").await?;
+                        say!("🔀 Switched orderbook to {}", pairs.join(", "));
+                    }
+                    _ => say!("⚠️ Unknown command {:?} (usage: symbol PAIR[,PAIR...])", line.trim()),
+                }
+                continue;
+            }
+        };
+
+        let (feed, frame) = message;
+        let received = frame.received;
+        match frame.message {
+            Message::Text(_) => {
+                if let Some(data) = frame.data {
+                    if data.get("channel").and_then(|c| c.as_str()) == Some(trades_channel.as_str())
+                        && data.get("event").and_then(|e| e.as_str()) == Some("update")
+                    {
+                        // Spot gửi 1 trade mỗi message, futures gửi mảng; size futures âm là bên bán
+                        let trades = match data.get("result") {
+                            Some(Value::Array(items)) => items.iter().collect(),
+                            Some(item) => vec![item],
+                            None => Vec::new(),
+                        };
+                        let mut share_prices = share_prices.lock().unwrap();
+                        for trade in trades {
+                            let pair = trade.get("currency_pair").or_else(|| trade.get("contract")).and_then(|s| s.as_str());
+                            let Some(pair) = pair.filter(|s| pairs.iter().any(|p| p == s)) else {
+                                continue;
+                            };
+                            let price = trade.get("price").and_then(|p| p.as_str()).and_then(|p| p.parse::<f64>().ok());
+                            let amount = trade
+                                .get("amount")
+                                .and_then(|a| a.as_str())
+                                .and_then(|a| a.parse::<f64>().ok())
+                                .or_else(|| trade.get("size").and_then(|s| s.as_f64()).map(f64::abs));
+                            let sp = share_prices.entry(pair.to_string()).or_default();
+                            if args.redundant_feed {
+                                let trade_id = trade.get("id").and_then(|i| i.as_u64());
+                                if trade_id.is_some_and(|id| sp.last_trade_id.is_some_and(|last| id <= last)) {
+                                    continue;
+                                }
+                                sp.last_trade_id = trade_id.or(sp.last_trade_id);
+                            }
+                            if let (Some(price), Some(amount)) = (price, amount) {
+                                sp.push_trade(price, amount);
+                            }
+                        }
+                        continue;
+                    }
+
+                    if data.get("channel").and_then(|c| c.as_str()) == Some(order_book_channel.as_str())
+                        && data.get("event").and_then(|e| e.as_str()) == Some("update")
+                    {
+                        // Spot dùng "s", futures dùng "contract"
+                        let result = data.get("result");
+                        let pair = result
+                            .and_then(|r| r.get("s").or_else(|| r.get("contract")))
+                            .and_then(|s| s.as_str())
+                            .filter(|s| pairs.iter().any(|p| p == s));
+                        if let (Some(result), Some(pair)) = (result, pair) {
+                            let mut share_prices = share_prices.lock().unwrap();
+                            let sp = share_prices.entry(pair.to_string()).or_default();
+                            sp.bids = parse_levels(result.get("bids"));
+                            sp.asks = parse_levels(result.get("asks"));
+                        }
+                        continue;
+                    }
+
+                    if data.get("channel").and_then(|c| c.as_str()) == Some(book_channel.as_str())
+                        && data.get("event").and_then(|e| e.as_str()) == Some("update")
+                    {
+                        if let Some(result) = data.get("result") {
+                            let pair = result
+                                .get("s")
+                                .and_then(|s| s.as_str())
+                                .filter(|s| pairs.iter().any(|p| p == s))
+                                .map(|s| s.to_string());
+                            if let Some(pair) = pair {
+                                // Chỉ nhận update mới hơn update đã nhận từ bất kỳ feed nào
+                                if feeds > 1 {
+                                    let update_id = result.get("u").and_then(|u| u.as_u64());
+                                    let mut share_prices = share_prices.lock().unwrap();
+                                    let sp = share_prices.entry(pair.clone()).or_default();
+                                    if update_id.is_some_and(|u| sp.last_update_id.is_some_and(|last| u <= last)) {
+                                        continue;
+                                    }
+                                    sp.last_update_id = update_id.or(sp.last_update_id);
+                                    if let Some(previous) = sp.source.filter(|previous| *previous != feed) {
+                                        let silent = feed_seen[previous].elapsed();
+                                        if silent >= FEED_STALL {
+                                            say!(
+                                                "🔀 {} feed failover: #{} -> #{} (feed #{} silent for {} ms)",
+                                                pair,
+                                                previous,
+                                                feed,
+                                                previous,
+                                                silent.as_millis()
+                                            );
+                                        }
+                                    }
+                                    sp.source = Some(feed);
+                                }
+                                let tick_count = tick_counts.entry(pair.clone()).or_insert(0);
+                                *tick_count += 1;
+                                let tick_count = *tick_count;
+                                let (best_ask, best_bid) = match args.book_channel {
+                                    BookChannel::BookTicker => (
+                                        result.get("a")
+                                            .and_then(|a| a.as_str())
+                                            .and_then(|a| a.parse::<f64>().ok())
+                                            .unwrap_or(0.0),
+                                        result.get("b")
+                                            .and_then(|b| b.as_str())
+                                            .and_then(|b| b.parse::<f64>().ok()),
+                                    ),
+                                    // "a"/"b" ở đây là danh sách level thay đổi, không phải giá tốt nhất
+                                    BookChannel::OrderBookUpdate => {
+                                        let mut share_prices = share_prices.lock().unwrap();
+                                        let book = &mut share_prices.entry(pair.clone()).or_default().local_book;
+                                        book.apply(result);
+                                        (book.best_ask().unwrap_or(0.0), book.best_bid())
+                                    }
+                                };
+
+                                // Book bị cross hoặc có giá 0 thường là feed hỏng tạm thời
+                                let crossed = best_bid.is_some_and(|bid| best_ask > 0.0 && bid >= best_ask);
+                                let zero = best_ask <= 0.0 || best_bid.is_some_and(|bid| bid <= 0.0);
+                                if crossed || zero {
+                                    let mut stats = managers[0].account.latency_stats.lock().unwrap();
+                                    if crossed {
+                                        stats.crossed_books += 1;
+                                    } else {
+                                        stats.zero_books += 1;
+                                    }
+                                    say!(
+                                        "⚠️ {} {} book: ask {} bid {:?}",
+                                        pair,
+                                        if crossed { "crossed" } else { "zero-price" },
+                                        best_ask,
+                                        best_bid
+                                    );
+                                }
+                                let book_bad = args.skip_crossed_book && (crossed || zero);
+
+                                let (old_price, mid, spread) = {
+                                    let mut share_prices = share_prices.lock().unwrap();
+                                    let sp = share_prices.entry(pair.clone()).or_default();
+                                    let old_price = sp.gia_mua_gate;
+                                    let time = Utc::now().format("%Y-%m-%d %H:%M:%S%.6f").to_string();
+                                    sp.gia_mua_gate = Some(best_ask);
+                                    sp.gia_ban_gate = best_bid;
+                                    sp.updated_at = Some(Instant::now());
+                                    decode_timing.record(received.elapsed(), args.decode_workers);
+                                    sp.server_time_ms = result.get("t").and_then(|t| t.as_u64());
+                                    if let Some(server_ms) = sp.server_time_ms.filter(|_| args.measure_book_latency) {
+                                        let account = &managers[0].account;
+                                        // Lùi về lúc frame tới, không tính thời gian decode
+                                        let received_ms = account.server_ts_ms() - received.elapsed().as_millis() as i64;
+                                        account.latency_stats.lock().unwrap().record_book_latency((received_ms - server_ms as i64) as f64);
+                                    }
+                                    sp.orderbook_ready = true;
+
+                                    if let Some(book_log) = &book_log {
+                                        let snapshot = BookSnapshot { pair: &pair, time: &time, ask: sp.gia_mua_gate, bid: sp.gia_ban_gate, server_time_ms: sp.server_time_ms };
+                                        let line = serde_json::to_string(&snapshot)?;
+                                        if let Err(e) = writeln!(book_log.lock().unwrap(), "{}", line) {
+                                            error!("Failed to write book log: {}", e);
+                                        }
+                                    }
+                                    sp.time_gia_gate = Some(time);
+                                    (old_price, sp.quote_mid(), sp.quote_spread())
+                                };
+
+                                // Chỉ in khi có thay đổi đáng kể hoặc mỗi 5 giây
+                                let current_time = Instant::now();
+                                let last_print = last_price_print.entry(pair.clone()).or_insert(current_time);
+                                let should_print = old_price.is_none_or(|old| (best_ask - old).abs() > 0.001)
+                                    || current_time.duration_since(*last_print).as_secs() > 5;
+                                // Hết --book-print-limit thì im lặng để log chỉ còn order event
+                                let should_print = should_print && args.book_print_limit.is_none_or(|n| book_prints < n);
+                                let should_print = should_print
+                                    && args.book_print_hz.is_none_or(|hz| {
+                                        last_book_print.is_none_or(|at| current_time.duration_since(at).as_secs_f64() >= 1.0 / hz)
+                                    });
+
+                                if should_print {
+                                    match (best_bid, mid, spread) {
+                                        (Some(bid), Some(mid), Some((spread, bps))) => say!(
+                                            "📊 Orderbook updated - {} ask price: {} bid: {} mid: {} spread: {} ({:.2} bps)",
+                                            pair,
+                                            best_ask,
+                                            bid,
+                                            mid,
+                                            format_decimal(spread),
+                                            bps
+                                        ),
+                                        _ => say!("📊 Orderbook updated - {} ask price: {}", pair, best_ask),
+                                    }
+                                    *last_print = current_time;
+                                    last_book_print = Some(current_time);
+                                    book_prints += 1;
+                                    if args.book_print_limit == Some(book_prints) {
+                                        say!("🔇 Book print limit {} reached, suppressing further orderbook prints", book_prints);
+                                    }
+                                }
+
+                                // Chỉ đặt lệnh 1 lần khi có giá, mọi connection đã warm và chưa đặt lệnh
+                                let authenticated = *warm.borrow();
+                                let placed = state.order_placed.lock().unwrap().contains(&pair);
+                                
+                                // Trigger theo số tick nếu có cấu hình, ngược lại theo thời gian chờ
+                                let trigger_ready = args.trigger_after_ticks.is_none_or(|n| tick_count >= n);
+
+                                // --compare-symbols: fire 1 lần cho cả nhóm khi mọi cặp đều có giá (và đủ tick)
+                                let compare = args.compare_symbols.is_some();
+                                let group_ready = !compare || {
+                                    let share_prices = share_prices.lock().unwrap();
+                                    pairs.iter().all(|p| {
+                                        share_prices.get(p).and_then(|sp| sp.gia_mua_gate).is_some_and(|ask| ask > 0.0)
+                                            && args.trigger_after_ticks.is_none_or(|n| tick_counts.get(p).is_some_and(|c| *c >= n))
+                                    })
+                                };
+
+                                if book_bad {
+                                    // Không trigger trên update hỏng, chờ update kế tiếp
+                                } else if compare && !placed && authenticated && group_ready {
+                                    state.order_placed.lock().unwrap().extend(pairs.iter().cloned());
+                                    let delay = match args.trigger_after_ticks {
+                                        Some(_) => Duration::ZERO,
+                                        None => {
+                                            say!("⏰ Waiting {} seconds before placing paired orders...", args.wait_seconds);
+                                            Duration::from_secs(args.wait_seconds)
+                                        }
+                                    };
+                                    let fire = fire_compare(managers.clone(), share_prices.clone(), pairs.clone(), args.clone(), delay);
+                                    let account = managers[0].account.clone();
+                                    let group = pairs.len();
+                                    tokio::spawn(async move {
+                                        fire.await;
+                                        account.fire_phases_done.fetch_add(group, Ordering::Relaxed);
+                                    });
+                                } else if !compare && !placed && best_ask > 0.0 && authenticated && trigger_ready {
+                                    state.order_placed.lock().unwrap().insert(pair.clone());
+                                    let delay = match args.trigger_after_ticks {
+                                        Some(n) => {
+                                            say!("🎯 {} tick {} reached (trigger after {} ticks), placing order...", pair, tick_count, n);
+                                            Duration::ZERO
+                                        }
+                                        None => {
+                                            say!("⏰ Waiting {} seconds before placing order...", args.wait_seconds);
+                                            Duration::from_secs(args.wait_seconds)
+                                        }
+                                    };
+                                    
+                                    let fire = fire_orders(
+                                        managers.clone(),
+                                        share_prices.clone(),
+                                        pair.clone(),
+                                        args.clone(),
+                                        best_ask,
+                                        delay,
+                                    );
+                                    let account = managers[0].account.clone();
+                                    tokio::spawn(async move {
+                                        fire.await;
+                                        account.fire_phases_done.fetch_add(1, Ordering::Relaxed);
+                                    });
+                                } else if !placed && should_print {
+                                    if best_ask <= 0.0 {
+                                        say!("⚠️ Not placing order: Invalid price {}", best_ask);
+                                    } else if !authenticated {
+                                        say!("⚠️ Not placing order: Trading connections not authenticated yet");
+                                    } else if !group_ready {
+                                        say!("⏳ Not placing order: waiting for prices on all of {}", pairs.join(", "));
+                                    } else if let Some(n) = args.trigger_after_ticks {
+                                        say!("⏳ Not placing order: Tick {}/{}", tick_count, n);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Message::Close(frame) => {
+                let label = format!("orderbook#{}", feed);
+                if !close_allows_reconnect(&label, frame.as_ref(), &args.fatal_close_codes) {
+                    return Err(Error::Closed(label).into());
+                }
+                if feeds == 1 {
+                    break;
+                }
+                say!("⚠️ Orderbook feed #{} closed, continuing on the other feed", feed);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Connection đang authenticated lúc fire; sau --warm-timeout có thể thiếu các
+/// connection chưa lên
+fn ready_managers(managers: &[Arc<ConnectionManager>]) -> Vec<Arc<ConnectionManager>> {
+    managers.iter().filter(|m| m.is_authenticated()).cloned().collect()
+}
+
+/// Fire phase của một cặp: chờ `delay`, đọc lại quote mới nhất rồi mỗi connection
+/// đặt `count` lệnh đồng thời với các connection khác
+async fn fire_orders(
+    managers: Vec<Arc<ConnectionManager>>,
+    share_prices: Arc<Mutex<HashMap<String, SharePrice>>>,
+    pair: String,
+    args: Arc<Args>,
+    trigger_ask: f64,
+    delay: Duration,
+) {
+    sleep(delay).await;
+    let managers = ready_managers(&managers);
+    if managers.is_empty() {
+        say!("❌ Not placing {} orders: no trading connection is authenticated", pair);
+        return;
+    }
+    say!("🚀 Fire phase: placing {} orders on {} warm connection(s)", pair, managers.len());
+
+    // Đọc lại giá mới nhất sau khi chờ thay vì dùng giá lúc trigger
+    let max_quote_age = Duration::from_millis(args.max_quote_age_ms);
+    let quote = share_prices
+        .lock()
+        .unwrap()
+        .get(&pair)
+        .and_then(|sp| Some((sp.gia_mua_gate?, sp.gia_ban_gate, sp.updated_at?, sp.server_time_ms)));
+    let (best_ask, best_bid, server_time_ms) = match quote {
+        Some((ask, bid, updated_at, server_time_ms)) if updated_at.elapsed() <= max_quote_age && ask > 0.0 => {
+            (ask, bid, server_time_ms)
+        }
+        Some((ask, _, updated_at, _)) => {
+            say!(
+                "⏭️ Skipping order: latest {} quote {} is {} ms old (max {} ms)",
+                pair,
+                ask,
+                updated_at.elapsed().as_millis(),
+                max_quote_age.as_millis()
+            );
+            return;
+        }
+        None => {
+            say!("⏭️ Skipping order: no {} quote after wait", pair);
+            return;
+        }
+    };
+    say!("💲 Using latest {} ask {} (triggered at {})", pair, best_ask, trigger_ask);
+    managers[0].account.trace_decision(&pair, best_ask, best_bid);
+    if let Some(max_pct) = args.abort_if_moved {
+        let moved_pct = (best_ask - trigger_ask) / trigger_ask * 100.0;
+        if moved_pct.abs() > max_pct {
+            say!(
+                "🛑 Aborting order: {} ask moved {:+.4}% during the wait ({} -> {}, limit {}%)",
+                pair,
+                moved_pct,
+                trigger_ask,
+                best_ask,
+                max_pct
+            );
+            return;
+        }
+    }
+    // Tuổi của quote theo đồng hồ server (đã trừ clock offset đo được)
+    if let Some(server_time_ms) = server_time_ms {
+        let age_ms = managers[0].account.quote_age_ms(server_time_ms);
+        say!("🕰 Quote age vs server t: {} ms (offset-adjusted now - book_ticker t)", age_ms);
+    }
+
+    // Book mỏng thì lệnh marketable khớp giá xấu, bỏ để dữ liệu slippage sạch
+    if let Some(max) = args.max_spread_bps {
+        match best_bid.and_then(|bid| SharePrice::spread_bps(best_ask, bid)) {
+            Some(spread) if spread <= max => {
+                say!("📏 {} spread {:.2} bps <= {:.2} bps", pair, spread, max);
+            }
+            Some(spread) => {
+                say!("⏭️ Skipping order: {} spread {:.2} bps > {:.2} bps (ask {}, bid {:?})", pair, spread, max, best_ask, best_bid);
+                return;
+            }
+            None => {
+                say!("⏭️ Skipping order: --max-spread-bps needs a valid {} bid", pair);
+                return;
+            }
+        }
+    }
+
+    if args.skip_crossed_book && best_bid.is_some_and(|bid| bid <= 0.0 || bid >= best_ask) {
+        say!("⏭️ Skipping order: {} book is crossed or zero (ask {}, bid {:?})", pair, best_ask, best_bid);
+        return;
+    }
+
+    // --price/--price-pct-of-mid thay giá từ book, các kiểm tra phía trên vẫn dựa trên book
+    let buy_price = match (args.price, args.price_pct_of_mid) {
+        (Some(price), _) => {
+            say!("💲 Using --price {} for {} BUY (book ask {})", price, pair, best_ask);
+            price
+        }
+        (None, Some(pct)) => match best_bid.and_then(|bid| SharePrice::mid(best_ask, bid)) {
+            Some(mid) => {
+                let raw = mid * pct / 100.0;
+                let (price, _) = managers[0].account.format_order_values(&pair, Side::Buy, raw, SO_COIN_DANH);
+                let price = price.parse().unwrap_or(raw);
+                say!("💲 {} BUY at {}% of mid {} -> {} (ask {}, bid {:?})", pair, pct, mid, price, best_ask, best_bid);
+                price
+            }
+            None => {
+                say!("⏭️ Skipping order: --price-pct-of-mid needs a valid {} bid", pair);
+                return;
+            }
+        },
+        (None, None) => best_ask,
+    };
+
+    // Bước SELL của --scenario không ghi giá thì dùng bid của book
+    let book_bid = best_bid.filter(|bid| *bid > 0.0);
+
+    // --alternate-sides: lệnh thứ chẵn BUY, lệnh thứ lẻ SELL @ bid của cùng quote
+    let alternate_bid = match (args.alternate_sides, book_bid) {
+        (false, _) => None,
+        (true, Some(bid)) => {
+            say!("🔁 Alternating {} BUY @ {} / SELL @ {}", pair, buy_price, bid);
+            Some(bid)
+        }
+        (true, None) => {
+            say!("⏭️ Skipping order: --alternate-sides needs a valid {} bid", pair);
+            return;
+        }
+    };
+
+    // --both-sides: cùng một quote (cùng thời điểm quyết định) cho cả lệnh mua và bán
+    let best_bid = match (args.both_sides, best_bid) {
+        (false, _) => None,
+        (true, Some(bid)) if bid > 0.0 => {
+            say!("💲 Using latest {} bid {} for the paired SELL", pair, bid);
+            Some(bid)
+        }
+        (true, _) => {
+            say!("⏭️ Skipping order: --both-sides needs a valid {} bid", pair);
+            return;
+        }
+    };
+
+    if args.with_trades {
+        match share_prices.lock().unwrap().get(&pair).and_then(|sp| sp.vwap()) {
+            Some((vwap, n)) => say!(
+                "📈 Recent {} VWAP {:.8} over {} trade(s) ({}s window), ask vs VWAP {:+.4}%",
+                pair,
+                vwap,
+                n,
+                TRADE_WINDOW.as_secs(),
+                (best_ask - vwap) / vwap * 100.0
+            ),
+            None => say!("📈 No recent {} trades in the last {}s", pair, TRADE_WINDOW.as_secs()),
+        }
+    }
+
+    // Chỉ đặt lệnh khi imbalance của book đủ thuận lợi tại thời điểm quyết định,
+    // và kiểm tra lại ngay trước mỗi lần gửi vì book có thể đổi trong lúc chờ
+    let min_imbalance = args.min_imbalance;
+    if let Some(min) = min_imbalance {
+        match imbalance_gate(&share_prices, &pair, min) {
+            Ok(value) => say!("⚖️ Book imbalance {:.4} > {:.4}, placing order", value, min),
+            Err(reason) => {
+                say!("⏭️ Skipping order: {} after wait", reason);
+                return;
+            }
+        }
+    }
+    
+    let symbol = pair.clone();
+    let amount = args.order_amount(&pair, buy_price);
+    args.log_order_amount(&pair, buy_price, amount);
+    let count = args.order_count();
+    let barrier = Arc::new(tokio::sync::Barrier::new(managers.len()));
+    let sync = args.sync_fire;
+    let mut tasks = Vec::new();
+    for manager in managers {
+        let symbol = symbol.clone();
+        let barrier = barrier.clone();
+        let share_prices = share_prices.clone();
+        tasks.push(tokio::spawn(async move {
+            if sync && !sync_fire(&barrier, &manager, &share_prices, std::slice::from_ref(&symbol)).await {
+                return;
+            }
+            let imbalance_ok = |n: u64| match min_imbalance.map(|min| imbalance_gate(&share_prices, &symbol, min)) {
+                Some(Err(reason)) => {
+                    say!("⏭️ [{}] Skipping order {}: {} at send time", manager.label(), n, reason);
+                    false
+                }
+                _ => true,
+            };
+            let started = Instant::now();
+            if let Some(scenario) = &manager.account.args.scenario {
+                if !imbalance_ok(1) {
+                    return;
+                }
+                run_scenario(&manager, scenario, &symbol, amount, buy_price, book_bid, started).await;
+                return;
+            }
+            for i in 0..count {
+                if i > 0 {
+                    manager.pace_next_order().await;
+                    if manager.duration_reached(started) {
+                        break;
+                    }
+                }
+                if !imbalance_ok(i + 1) {
+                    continue;
+                }
+                let (place_buy, sell_price) = match alternate_bid {
+                    Some(bid) if i % 2 == 1 => (false, Some(bid)),
+                    Some(_) => (true, None),
+                    None => (true, best_bid),
+                };
+                let buy = async {
+                    if place_buy {
+                        manager.create_order("BUY", &symbol, amount, buy_price, "limit", "gtc", None, None).await
+                    } else {
+                        Ok(())
+                    }
+                };
+                let sell = async {
+                    match sell_price {
+                        Some(bid) => manager.create_order("SELL", &symbol, amount, bid, "limit", "gtc", None, None).await,
+                        None => Ok(()),
+                    }
+                };
+                let (buy, sell) = tokio::join!(buy, sell);
+                for result in [buy, sell] {
+                    if let Err(e) = result {
+                        say!("❌ [{}] Order not placed: {}", manager.label(), e);
+                    }
+                }
+            }
+        }));
+    }
+    futures_util::future::join_all(tasks).await;
+}
+
+/// --min-imbalance: imbalance hiện tại của book nếu vượt ngưỡng, ngược lại lý do bỏ lệnh
+fn imbalance_gate(share_prices: &Mutex<HashMap<String, SharePrice>>, pair: &str, min: f64) -> Result<f64, String> {
+    match share_prices.lock().unwrap().get(pair).and_then(|sp| sp.imbalance()) {
+        Some(value) if value > min => Ok(value),
+        Some(value) => Err(format!("book imbalance {:.4} <= {:.4}", value, min)),
+        None => Err("no depth data".to_string()),
+    }
+}
+
+/// Gửi lần lượt các bước của --scenario trên một connection đã warm; giá mặc định
+/// là giá BUY của lệnh thường (ask hoặc --price) hoặc bid của book cho SELL
+async fn run_scenario(
+    manager: &ConnectionManager,
+    scenario: &Scenario,
+    symbol: &str,
+    default_amount: f64,
+    buy_price: f64,
+    book_bid: Option<f64>,
+    started: Instant,
+) {
+    for (index, step) in scenario.steps.iter().enumerate() {
+        let number = index + 1;
+        if index > 0 {
+            manager.pace_next_order().await;
+            if manager.duration_reached(started) {
+                break;
+            }
+        }
+        if !step.delay.is_zero() {
+            sleep(step.delay).await;
+        }
+        let price = match (step.price, step.side) {
+            (Some(price), _) => price,
+            (None, Side::Buy) => buy_price,
+            (None, Side::Sell) => match book_bid {
+                Some(bid) => bid,
+                None => {
+                    say!("⏭️ [{}] Scenario step {} skipped: SELL without price needs a valid {} bid", manager.label(), number, symbol);
+                    continue;
+                }
+            },
+        };
+        let amount = step.amount.unwrap_or(default_amount);
+        say!(
+            "📜 [{}] Scenario step {}/{}: {} {} {} {} @ {} ({})",
+            manager.label(),
+            number,
+            scenario.steps.len(),
+            step.side,
+            step.order_type,
+            amount,
+            symbol,
+            price,
+            step.time_in_force
+        );
+        let side = step.side.to_string();
+        if let Err(e) = manager
+            .create_order(&side, symbol, amount, price, &step.order_type, &step.time_in_force, None, Some(number))
+            .await
+        {
+            say!("❌ [{}] Scenario step {} not placed: {}", manager.label(), number, e);
+        }
+    }
+}
+
+/// --sync-fire: chờ connection của `manager` authenticated và mọi cặp trong `pairs` có
+/// quote còn mới (--max-quote-age-ms), rồi chờ mọi connection khác ở barrier.
+/// Connection không sẵn sàng trong 5s vẫn qua barrier (để không chặn các connection
+/// khác) nhưng trả false để bỏ lệnh
+async fn sync_fire(
+    barrier: &tokio::sync::Barrier,
+    manager: &ConnectionManager,
+    share_prices: &Mutex<HashMap<String, SharePrice>>,
+    pairs: &[String],
+) -> bool {
+    let max_quote_age = Duration::from_millis(manager.account.args.max_quote_age_ms);
+    let fresh = || {
+        let share_prices = share_prices.lock().unwrap();
+        pairs.iter().all(|pair| {
+            share_prices.get(pair).is_some_and(|sp| {
+                sp.gia_mua_gate.is_some_and(|ask| ask > 0.0) && sp.updated_at.is_some_and(|at| at.elapsed() <= max_quote_age)
+            })
+        })
+    };
+    let deadline = Duration::from_secs(5);
+    let authenticated = manager.wait_authenticated(deadline).await;
+    let ready = authenticated
+        && tokio::time::timeout(deadline, async {
+            while !fresh() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .is_ok();
+    if barrier.wait().await.is_leader() {
+        say!("🏁 Synchronized fire at {}", Utc::now().format("%H:%M:%S%.6f"));
+    }
+    if !authenticated {
+        say!("⏭️ [{}] Not authenticated at the fire barrier, skipping its orders", manager.label());
+    } else if !ready {
+        say!("⏭️ [{}] No fresh {} quote at the fire barrier, skipping its orders", manager.label(), pairs.join("/"));
+    }
+    ready
+}
+
+/// Fire phase của --compare-symbols: đọc quote của cả 2 cặp tại cùng một thời điểm
+/// quyết định, rồi mỗi connection gửi 2 lệnh đồng thời (size theo --amount/--target-notional)
+async fn fire_compare(
+    managers: Vec<Arc<ConnectionManager>>,
+    share_prices: Arc<Mutex<HashMap<String, SharePrice>>>,
+    pairs: Vec<String>,
+    args: Arc<Args>,
+    delay: Duration,
+) {
+    sleep(delay).await;
+    let managers = ready_managers(&managers);
+    if managers.is_empty() {
+        say!("❌ Not placing paired orders: no trading connection is authenticated");
+        return;
+    }
+
+    let max_quote_age = Duration::from_millis(args.max_quote_age_ms);
+    let decided_at = Utc::now();
+    let mut asks = Vec::new();
+    {
+        let share_prices = share_prices.lock().unwrap();
+        for pair in &pairs {
+            let quote = share_prices.get(pair).and_then(|sp| Some((sp.gia_mua_gate?, sp.updated_at?)));
+            match quote {
+                Some((ask, updated_at)) if ask > 0.0 && updated_at.elapsed() <= max_quote_age => asks.push(ask),
+                _ => {
+                    say!("⏭️ Skipping paired orders: no fresh {} quote (max {} ms)", pair, max_quote_age.as_millis());
+                    return;
+                }
+            }
+        }
+    }
+    let quotes: Vec<String> = pairs.iter().zip(&asks).map(|(pair, ask)| format!("{} ask {}", pair, ask)).collect();
+    say!("🎯 Paired decision at {}: {}", decided_at.format("%H:%M:%S%.6f"), quotes.join(", "));
+
+    let amounts: Vec<f64> = pairs.iter().zip(&asks).map(|(pair, ask)| args.order_amount(pair, *ask)).collect();
+    for ((pair, ask), amount) in pairs.iter().zip(&asks).zip(&amounts) {
+        args.log_order_amount(pair, *ask, *amount);
+    }
+
+    let count = args.order_count();
+    let barrier = Arc::new(tokio::sync::Barrier::new(managers.len()));
+    let sync = args.sync_fire;
+    let mut tasks = Vec::new();
+    for manager in managers {
+        let pairs = pairs.clone();
+        let asks = asks.clone();
+        let amounts = amounts.clone();
+        let barrier = barrier.clone();
+        let share_prices = share_prices.clone();
+        tasks.push(tokio::spawn(async move {
+            if sync && !sync_fire(&barrier, &manager, &share_prices, &pairs).await {
+                return;
+            }
+            let started = Instant::now();
+            for i in 0..count {
+                if i > 0 {
+                    manager.pace_next_order().await;
+                    if manager.duration_reached(started) {
+                        break;
+                    }
+                }
+                let decision = manager.account.decision_seq.fetch_add(1, Ordering::Relaxed);
+                let orders = pairs.iter().zip(&asks).zip(&amounts).map(|((pair, ask), amount)| {
+                    manager.create_order("BUY", pair, *amount, *ask, "limit", "gtc", Some(decision), None)
+                });
+                for result in futures_util::future::join_all(orders).await {
+                    if let Err(e) = result {
+                        say!("❌ [{}] Order not placed: {}", manager.label(), e);
+                    }
+                }
+            }
+        }));
+    }
+    futures_util::future::join_all(tasks).await;
+}
+
+async fn start_trading_ws(manager: Arc<ConnectionManager>) -> Result<()> {
+    let account = manager.account.clone();
+    let ws_url = account.args.market.ws_url();
+    // Số lần reconnect thất bại liên tiếp, reset khi connect + auth thành công
+    let mut failed_attempts: u32 = 0;
+    
+    loop {
+        manager.emit(ConnState::Connecting);
+        match connect_ws(Url::parse(ws_url)?, &account.args).await {
+            Ok(ws_stream) => {
+                say!("🔌 Connecting to Gate.io WS for trading...");
+                if account.args.log_conn_info {
+                    log_conn_info(&manager.label(), &ws_stream);
+                }
+                let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+                
+                say!("✅ [{}] Connected to Gate.io WS", manager.label());
+                manager.emit(ConnState::Connected);
+                
+                // Authenticate
+                match account.authenticate(&manager, &mut ws_sender).await {
+                    Ok(()) => {}
+                    // Credentials sai thì reconnect cũng không giúp được
+                    Err(e @ Error::Auth(_)) => return Err(e.into()),
+                    Err(e) => {
+                        error!("Authentication failed: {}", e);
+                        failed_attempts += 1;
+                        manager.emit(ConnState::Disconnected);
+                        reconnect_backoff(&manager, failed_attempts).await?;
+                        continue;
+                    }
+                }
+                failed_attempts = 0;
+                *manager.ws_sender.lock().await = Some(ws_sender);
+                // Per-message không có login: subscribe ngay vì mỗi request tự ký
+                if account.args.auth_mode == AuthMode::PerMessage && !account.args.subscribe.is_empty() {
+                    if let Err(e) = manager.subscribe_private().await {
+                        say!("❌ [{}] Private subscribe failed: {}", manager.label(), e);
+                    }
+                }
+                
+                // Ping định kỳ trên socket này; guard abort task khi connection kết thúc
+                let pinger = manager.clone();
+                let _pinger = AbortOnDrop(tokio::spawn(async move {
+                    loop {
+                        sleep(Duration::from_secs(30)).await;
+                        match pinger.send_ping().await {
+                            Ok(()) => say!("📡 [{}] Ping sent", pinger.label()),
+                            Err(e) => say!("⚠️ [{}] Ping not sent: {}", pinger.label(), e),
+                        }
+                    }
+                }));
+                
+                // Handle messages
+                while let Some(message) = ws_receiver.next().await {
+                    // --inject delay-response: phản hồi "tới muộn" N ms, latency đo gồm cả phần trễ
+                    if let Some(delay) = account.args.inject_delay_response() {
+                        sleep(delay).await;
+                    }
+                    let received = Timestamp::now();
+                    manager.progress.touch();
+                    match message {
+                        Ok(Message::Text(text)) => {
+                            // Lỗi mang theo channel/event và preview của frame gây lỗi
+                            match account
+                                .handle_message(&manager, &text, received)
+                                .with_context(|| format!("[{}] {}", manager.label(), frame_context(&text)))
+                            {
+                                Ok(Some(FollowUp::Amend { order_id, currency_pair })) => {
+                                    if let Err(e) = manager
+                                        .amend_order(&order_id, &currency_pair, account.args.amend_price, account.args.amend_amount)
+                                        .await
+                                    {
+                                        say!("❌ [{}] Amend not sent: {}", manager.label(), e);
+                                    }
+                                }
+                                Ok(Some(FollowUp::SubscribePrivate)) => {
+                                    if let Err(e) = manager.subscribe_private().await {
+                                        say!("❌ [{}] Private subscribe failed: {}", manager.label(), e);
+                                    }
+                                }
+                                Ok(Some(FollowUp::RetryAuth)) => {
+                                    if let Err(e) = manager.reauthenticate().await {
+                                        say!("❌ [{}] Auth retry not sent: {}", manager.label(), e);
+                                    }
+                                }
+                                Ok(Some(FollowUp::RelayBook(data))) => {
+                                    manager.relay_book(BookFrame { received: received.instant, message: Message::Text(text), data: Some(data) });
+                                }
+                                Ok(None) => {}
+                                Err(e) => error!("Error handling message: {:#}", e),
+                            }
+                        }
+                        Ok(Message::Close(frame)) => {
+                            if !close_allows_reconnect(&manager.label(), frame.as_ref(), &account.args.fatal_close_codes) {
+                                *manager.ws_sender.lock().await = None;
+                                manager.mark_disconnected();
+                                account.expire_pending("connection closed", |_, p| p.conn == manager.index);
+                                return Err(Error::Closed(manager.label()).into());
+                            }
+                            break;
+                        }
+                        Err(e) => {
+                            error!("WebSocket error: {}", e);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                *manager.ws_sender.lock().await = None;
+                manager.mark_disconnected();
+                // Phản hồi chỉ về trên connection đã gửi, connection mới sẽ không nhận được
+                account.expire_pending("connection lost", |_, p| p.conn == manager.index);
+            }
+            Err(e) => {
+                error!("Failed to connect: {}", e);
+                failed_attempts += 1;
+                manager.emit(ConnState::Disconnected);
+            }
+        }
+        
+        reconnect_backoff(&manager, failed_attempts).await?;
+    }
+}
+
+/// Mở TCP socket với các option của --tcp-nodelay/--so-sndbuf/--so-rcvbuf rồi mới
+/// bắt tay TLS + WebSocket; connect_async không cho set buffer trước khi connect
+async fn connect_ws(url: Url, args: &Args) -> Result<WsStream, tungstenite::Error> {
+    let host = url.host_str().ok_or(tungstenite::Error::Url(tungstenite::error::UrlError::NoHostName))?.to_string();
+    let port = url.port_or_known_default().ok_or(tungstenite::Error::Url(tungstenite::error::UrlError::UnsupportedUrlScheme))?;
+    let mut last_error = None;
+    for addr in tokio::net::lookup_host((host.as_str(), port)).await? {
+        let socket = if addr.is_ipv4() { tokio::net::TcpSocket::new_v4()? } else { tokio::net::TcpSocket::new_v6()? };
+        if let Some(size) = args.so_sndbuf {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = args.so_rcvbuf {
+            socket.set_recv_buffer_size(size)?;
+        }
+        match socket.connect(addr).await {
+            Ok(stream) => {
+                stream.set_nodelay(args.tcp_nodelay)?;
+                // TLS tự làm (thay vì client_async_tls) để Inflater nằm giữa TLS và tungstenite
+                let inner = match url.scheme() {
+                    // native-tls không expose TLS version/cipher suite, rustls thì có
+                    "wss" if args.log_conn_info => {
+                        let server_name = rustls::pki_types::ServerName::try_from(host.clone())
+                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+                        TransportStream::Rustls(Box::new(rustls_connector()?.connect(server_name, stream).await?))
+                    }
+                    "wss" => {
+                        let connector = tokio_native_tls::native_tls::TlsConnector::new().map_err(TlsError::Native)?;
+                        let tls = tokio_native_tls::TlsConnector::from(connector).connect(&host, stream).await.map_err(TlsError::Native)?;
+                        TransportStream::Native(MaybeTlsStream::NativeTls(tls))
+                    }
+                    _ => TransportStream::Native(MaybeTlsStream::Plain(stream)),
+                };
+                let inflate = args.compress.then(|| Box::new(Inflater::new(args.ws_max_message_size)));
+                let mut request = url.as_str().into_client_request()?;
+                if args.compress {
+                    request.headers_mut().insert("Sec-WebSocket-Extensions", tungstenite::http::HeaderValue::from_static("permessage-deflate"));
+                }
+                let (ws_stream, response) = client_async_with_config(request, WsTransport { inner, inflate }, Some(args.ws_config())).await?;
+                if args.compress {
+                    let accepted = response
+                        .headers()
+                        .get("Sec-WebSocket-Extensions")
+                        .and_then(|v| v.to_str().ok())
+                        .is_some_and(|v| v.contains("permessage-deflate"));
+                    if accepted {
+                        say!("🗜 {}: server accepted permessage-deflate", host);
+                    } else {
+                        say!("🗜 {}: server declined permessage-deflate, frames are uncompressed", host);
+                    }
+                }
+                return Ok(ws_stream);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} did not resolve to any address", host)))
+        .into())
+}
+
+/// Connector rustls của --log-conn-info, tin các root CA của hệ thống như native-tls
+fn rustls_connector() -> std::io::Result<tokio_rustls::TlsConnector> {
+    use rustls::pki_types::pem::PemObject;
+
+    static CONNECTOR: OnceLock<tokio_rustls::TlsConnector> = OnceLock::new();
+    if let Some(connector) = CONNECTOR.get() {
+        return Ok(connector.clone());
+    }
+    let mut roots = rustls::RootCertStore::empty();
+    if let Some(file) = openssl_probe::probe().cert_file {
+        roots.add_parsable_certificates(rustls::pki_types::CertificateDer::pem_file_iter(file).map_err(std::io::Error::other)?.flatten());
+    }
+    if roots.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no system CA certificates found for rustls"));
+    }
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(std::io::Error::other)?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(CONNECTOR.get_or_init(|| tokio_rustls::TlsConnector::from(Arc::new(config))).clone())
+}
+
+/// --log-conn-info: địa chỉ edge node thực tế, TLS version và cipher suite đã negotiate
+fn log_conn_info(label: &str, stream: &WsStream) {
+    let (tcp, tls) = match &stream.get_ref().inner {
+        TransportStream::Native(MaybeTlsStream::Plain(tcp)) => (tcp, None),
+        TransportStream::Rustls(tls) => {
+            let (tcp, tls) = tls.get_ref();
+            (tcp, Some(tls))
+        }
+        _ => {
+            say!("🔎 [{}] Connection info unavailable for this stream type", label);
+            return;
+        }
+    };
+    let addr = |a: std::io::Result<std::net::SocketAddr>| a.map_or_else(|e| format!("unknown ({})", e), |a| a.to_string());
+    say!("🔎 [{}] Remote {} (local {})", label, addr(tcp.peer_addr()), addr(tcp.local_addr()));
+    match tls {
+        Some(tls) => {
+            let version = tls.protocol_version().map_or("unknown".to_string(), |v| format!("{:?}", v));
+            let suite = tls.negotiated_cipher_suite().map_or("unknown".to_string(), |s| format!("{:?}", s.suite()));
+            say!("🔎 [{}] TLS {}, cipher suite {}", label, version, suite);
+        }
+        None => say!("🔎 [{}] Plain TCP, no TLS", label),
+    }
+}
+
+/// Text frame không phải JSON (control/text frame lạ) chỉ log debug rồi bỏ qua,
+/// giống nhau ở cả orderbook và trading loop
+fn parse_frame(source: &str, text: &str) -> Option<Value> {
+    match serde_json::from_str(text) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            debug!("[{}] Ignoring unparseable frame: {} ({})", source, e, frame_context(text));
+            None
+        }
+    }
+}
+
+/// Ngữ cảnh của một frame để log lỗi: channel/event nếu tìm được trong text (kể cả
+/// khi JSON hỏng) và preview tối đa 200 ký tự, không cắt giữa ký tự UTF-8
+fn frame_context(text: &str) -> String {
+    let field = |key: &str| {
+        let pattern = format!("\"{}\"", key);
+        let rest = text[text.find(&pattern)? + pattern.len()..].trim_start().strip_prefix(':')?.trim_start();
+        let value = rest.strip_prefix('"')?;
+        Some(&value[..value.find('"')?])
+    };
+    let mut context = Vec::new();
+    if let Some(channel) = field("channel") {
+        context.push(format!("channel {}", channel));
+    }
+    if let Some(event) = field("event") {
+        context.push(format!("event {}", event));
+    }
+    let preview = truncate_str(text, 200);
+    let ellipsis = if preview.len() < text.len() { "…" } else { "" };
+    context.push(format!("frame: {}{}", preview, ellipsis));
+    context.join(", ")
+}
+
+/// Log close frame và quyết định theo --fatal-close-codes: true nếu được reconnect
+fn close_allows_reconnect(label: &str, frame: Option<&CloseFrame>, fatal_codes: &[u16]) -> bool {
+    let Some(frame) = frame else {
+        say!("🔌 [{}] WebSocket closed without a close frame, reconnecting", label);
+        return true;
+    };
+    let code = u16::from(frame.code);
+    if fatal_codes.contains(&code) {
+        say!("❌ [{}] WebSocket closed with fatal code {} ({:?}): {}", label, code, frame.code, frame.reason);
+        return false;
+    }
+    say!("🔌 [{}] WebSocket closed with code {} ({:?}): {}, reconnecting", label, code, frame.code, frame.reason);
+    true
+}
+
+/// Backoff mũ 3s, 6s, 12s... (tối đa 60s) theo số lần thất bại liên tiếp;
+/// trả lỗi khi vượt --max-reconnects
+async fn reconnect_backoff(manager: &ConnectionManager, failed_attempts: u32) -> Result<()> {
+    if let Some(max) = manager.account.args.max_reconnects {
+        if failed_attempts > max {
+            say!("❌ [{}] Giving up after {} consecutive failed reconnect attempts", manager.label(), max);
+            anyhow::bail!("{}: exceeded --max-reconnects {}", manager.label(), max);
+        }
+    }
+    let base = Duration::from_secs(3 * 2u64.pow(failed_attempts.saturating_sub(1).min(5))).min(Duration::from_secs(60));
+    // Jitter tới 20% để các connection không reconnect cùng lúc
+    let jitter: f64 = manager.account.rng.lock().unwrap().gen_range(0.0..0.2);
+    let delay = base.mul_f64(1.0 + jitter);
+    manager.emit(ConnState::Reconnecting);
+    manager.account.ops.lock().unwrap().reconnects += 1;
+    info!("🔄 Reconnecting in {:.1} seconds...", delay.as_secs_f64());
+    sleep(delay).await;
+    Ok(())
+}
+
+/// Transport dưới tungstenite: TCP/TLS, và với --compress thêm Inflater giải nén
+/// frame permessage-deflate (tungstenite 0.20 không hỗ trợ extension này)
+struct WsTransport {
+    inner: TransportStream,
+    inflate: Option<Box<Inflater>>,
+}
+
+/// TCP/TLS dưới WsTransport: stream của tokio-tungstenite, hoặc rustls với --log-conn-info
+enum TransportStream {
+    Native(MaybeTlsStream<TcpStream>),
+    Rustls(Box<tokio_rustls::client::TlsStream<TcpStream>>),
+}
+
+impl tokio::io::AsyncRead for TransportStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TransportStream::Native(s) => Pin::new(s).poll_read(cx, buf),
+            TransportStream::Rustls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for TransportStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            TransportStream::Native(s) => Pin::new(s).poll_write(cx, buf),
+            TransportStream::Rustls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TransportStream::Native(s) => Pin::new(s).poll_flush(cx),
+            TransportStream::Rustls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            TransportStream::Native(s) => Pin::new(s).poll_shutdown(cx),
+            TransportStream::Rustls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+impl tokio::io::AsyncRead for WsTransport {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &mut tokio::io::ReadBuf<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let WsTransport { inner, inflate } = self.get_mut();
+        let Some(inflater) = inflate else {
+            return Pin::new(inner).poll_read(cx, buf);
+        };
+        loop {
+            if inflater.out_pos < inflater.out.len() {
+                let n = buf.remaining().min(inflater.out.len() - inflater.out_pos);
+                buf.put_slice(&inflater.out[inflater.out_pos..inflater.out_pos + n]);
+                inflater.out_pos += n;
+                return std::task::Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0u8; 16 * 1024];
+            let mut read = tokio::io::ReadBuf::new(&mut chunk);
+            std::task::ready!(Pin::new(&mut *inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return std::task::Poll::Ready(Ok(()));
+            }
+            inflater.feed(read.filled())?;
+        }
+    }
+}
+
+impl tokio::io::AsyncWrite for WsTransport {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Giải nén permessage-deflate (RFC 7692) ở tầng byte: HTTP response của handshake đi
+/// thẳng, sau đó mỗi message có RSV1 được inflate và ghi lại thành frame thường (không
+/// RSV1) cho tungstenite. Client không nén chiều gửi, RFC cho phép
+struct Inflater {
+    decompress: flate2::Decompress,
+    // Số byte đã khớp của "\r\n\r\n" kết thúc HTTP response
+    handshake_matched: usize,
+    raw: Vec<u8>,
+    out: Vec<u8>,
+    out_pos: usize,
+    // Message nén bị phân mảnh: (opcode, payload nén gom qua các continuation frame)
+    pending: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    // Thời gian inflate: số message, tổng, max
+    inflated: u64,
+    inflate_total: Duration,
+    inflate_max: Duration,
+    compressed_bytes: u64,
+    inflated_bytes: u64,
+}
+
+impl Inflater {
+    const LOG_EVERY: u64 = 1000;
+
+    fn new(max_message_size: usize) -> Self {
+        Self {
+            decompress: flate2::Decompress::new(false),
+            handshake_matched: 0,
+            raw: Vec::new(),
+            out: Vec::new(),
+            out_pos: 0,
+            pending: None,
+            max_message_size,
+            inflated: 0,
+            inflate_total: Duration::ZERO,
+            inflate_max: Duration::ZERO,
+            compressed_bytes: 0,
+            inflated_bytes: 0,
+        }
+    }
+
+    fn too_large(what: &str) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} exceeds the max message size", what))
+    }
+
+    fn feed(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.out.drain(..self.out_pos);
+        self.out_pos = 0;
+        let mut bytes = bytes;
+        while self.handshake_matched < 4 && !bytes.is_empty() {
+            let b = bytes[0];
+            self.handshake_matched = match (self.handshake_matched, b) {
+                (0 | 2, b'\r') => self.handshake_matched + 1,
+                (1 | 3, b'\n') => self.handshake_matched + 1,
+                (_, b'\r') => 1,
+                _ => 0,
+            };
+            self.out.push(b);
+            bytes = &bytes[1..];
+        }
+        self.raw.extend_from_slice(bytes);
+        while let Some((header_len, frame_len)) = Self::frame_len(&self.raw, self.max_message_size)? {
+            if self.raw.len() < frame_len {
+                break;
+            }
+            let frame: Vec<u8> = self.raw.drain(..frame_len).collect();
+            self.handle_frame(frame, header_len)?;
+        }
+        Ok(())
+    }
+
+    /// (độ dài header, độ dài cả frame) nếu đã nhận đủ header. Độ dài payload được kiểm
+    /// tra ngay tại header để frame giả không làm `raw` phình ra vô hạn
+    fn frame_len(raw: &[u8], max_message_size: usize) -> std::io::Result<Option<(usize, usize)>> {
+        let Some(&second) = raw.get(1) else {
+            return Ok(None);
+        };
+        let (mut header_len, payload_len) = match second & 0x7f {
+            126 => match raw.get(2..4) {
+                Some(len) => (4, u16::from_be_bytes([len[0], len[1]]) as u64),
+                None => return Ok(None),
+            },
+            127 => match raw.get(2..10) {
+                Some(len) => (10, u64::from_be_bytes(len.try_into().unwrap())),
+                None => return Ok(None),
+            },
+            n => (2, n as u64),
+        };
+        if second & 0x80 != 0 {
+            header_len += 4;
+        }
+        if payload_len > max_message_size as u64 {
+            return Err(Self::too_large("frame"));
+        }
+        if raw.len() < header_len {
+            return Ok(None);
+        }
+        let frame_len = header_len.checked_add(payload_len as usize).ok_or_else(|| Self::too_large("frame"))?;
+        Ok(Some((header_len, frame_len)))
+    }
+
+    fn handle_frame(&mut self, frame: Vec<u8>, header_len: usize) -> std::io::Result<()> {
+        let (fin, rsv1, opcode) = (frame[0] & 0x80 != 0, frame[0] & 0x40 != 0, frame[0] & 0x0f);
+        let compressed = match opcode {
+            // Control frame không bao giờ nén, được phép chen giữa các mảnh
+            8.. => false,
+            0 => self.pending.is_some(),
+            _ => rsv1,
+        };
+        if !compressed {
+            self.out.extend_from_slice(&frame);
+            return Ok(());
+        }
+        let mut payload = frame[header_len..].to_vec();
+        if frame[1] & 0x80 != 0 {
+            let mask = &frame[header_len - 4..header_len];
+            payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= mask[i % 4]);
+        }
+        let (opcode, mut data) = match self.pending.take() {
+            Some((first, mut data)) => {
+                data.extend_from_slice(&payload);
+                (first, data)
+            }
+            None => (opcode, payload),
+        };
+        if data.len() > self.max_message_size {
+            return Err(Self::too_large("compressed message"));
+        }
+        if !fin {
+            self.pending = Some((opcode, data));
+            return Ok(());
+        }
+        let received = Instant::now();
+        self.compressed_bytes += data.len() as u64;
+        data.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+        let message = self.inflate(&data)?;
+        self.inflated_bytes += message.len() as u64;
+        self.out.push(0x80 | opcode);
+        match message.len() {
+            n @ 0..=125 => self.out.push(n as u8),
+            n @ 126..=0xffff => {
+                self.out.push(126);
+                self.out.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                self.out.push(127);
+                self.out.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+        self.out.extend_from_slice(&message);
+        self.record(received.elapsed());
+        Ok(())
+    }
+
+    fn inflate(&mut self, input: &[u8]) -> std::io::Result<Vec<u8>> {
+        let invalid = |e: flate2::DecompressError| std::io::Error::new(std::io::ErrorKind::InvalidData, e);
+        let mut message = Vec::with_capacity(input.len() * 4);
+        let start = self.decompress.total_in();
+        loop {
+            let before = ((self.decompress.total_in() - start) as usize, message.len());
+            let status = self.decompress.decompress_vec(&input[before.0..], &mut message, flate2::FlushDecompress::Sync).map_err(invalid)?;
+            if status == flate2::Status::StreamEnd {
+                // Server kết thúc deflate stream (BFINAL): message sau bắt đầu stream mới
+                self.decompress.reset(false);
+                return Ok(message);
+            }
+            if message.len() > self.max_message_size {
+                return Err(Self::too_large("inflated message"));
+            }
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if consumed == input.len() && message.len() < message.capacity() {
+                return Ok(message);
+            }
+            if message.len() == message.capacity() {
+                message.reserve(message.capacity().max(1024));
+            } else if before == (consumed, message.len()) {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "truncated permessage-deflate payload"));
+            }
+        }
+    }
+
+    fn record(&mut self, elapsed: Duration) {
+        self.inflated += 1;
+        self.inflate_total += elapsed;
+        self.inflate_max = self.inflate_max.max(elapsed);
+        if self.inflated.is_multiple_of(Self::LOG_EVERY) {
+            say!(
+                "🗜 Inflate: avg {:.1} µs, max {:.1} µs over {} messages ({} -> {} bytes, {:.1}x)",
+                self.inflate_total.as_secs_f64() * 1e6 / self.inflated as f64,
+                self.inflate_max.as_secs_f64() * 1e6,
+                self.inflated,
+                self.compressed_bytes,
+                self.inflated_bytes,
+                self.inflated_bytes as f64 / self.compressed_bytes.max(1) as f64
+            );
+        }
+    }
+}
+
+/// Secret lấy từ file hoặc keyring để không xuất hiện trong env/shell history;
+/// fallback về GATEIO_API_SECRET khi không cấu hình.
+fn load_api_secret(args: &Args) -> Result<String> {
+    let secret = if let Some(path) = &args.secret_file {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read API secret from {}", path.display()))?
+            .trim()
+            .to_string()
+    } else if let Some(user) = &args.keyring_user {
+        keyring::Entry::new(KEYRING_SERVICE, user)
+            .and_then(|entry| entry.get_password())
+            .with_context(|| format!("Failed to read API secret from keyring ({}/{})", KEYRING_SERVICE, user))?
+    } else {
+        env::var("GATEIO_API_SECRET")
+            .map_err(|_| anyhow::anyhow!("GATEIO_API_SECRET not found in environment"))?
+    };
+
+    if secret.is_empty() {
+        anyhow::bail!("API secret is empty");
+    }
+    Ok(secret)
+}
+
+/// Runtime multi-thread; với --cpu-affinity mỗi worker thread được pin vào một core
+pub fn build_runtime(args: &Args) -> Result<tokio::runtime::Runtime> {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+
+    if let Some(cores) = &args.cpu_affinity {
+        let available = core_affinity::get_core_ids().unwrap_or_default();
+        for &core in cores {
+            if !available.iter().any(|c| c.id == core) {
+                anyhow::bail!("--cpu-affinity: core {} is not available ({} core(s) on this machine)", core, available.len());
+            }
+        }
+        let cores = cores.clone();
+        let started = Arc::new(AtomicUsize::new(0));
+        builder.worker_threads(cores.len()).on_thread_start(move || {
+            // Worker thread được tạo trước; thread blocking (stdin...) tạo sau không bị pin
+            let index = started.fetch_add(1, Ordering::Relaxed);
+            if let Some(&core) = cores.get(index) {
+                if core_affinity::set_for_current(core_affinity::CoreId { id: core }) {
+                    say!("📌 Pinned tokio worker #{} to CPU core {}", index, core);
+                } else {
+                    say!("⚠️ Failed to pin tokio worker #{} to CPU core {}", index, core);
+                }
+            }
+        });
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Áp dụng --plain/--quiet-until-order cho output và khởi tạo env_logger; gọi một lần
+pub fn init_logging(args: &Args) {
+    PLAIN_OUTPUT.store(args.plain, Ordering::Relaxed);
+    QUIET_UNTIL_ORDER.store(args.quiet_until_order, Ordering::Relaxed);
+    let mut logger = env_logger::Builder::from_default_env();
+    if args.plain {
+        logger.format(|buf, record| {
+            writeln!(buf, "[{} {} {}] {}", buf.timestamp(), record.level(), record.target(), plain_text(&record.args().to_string()))
+        });
+    }
+    logger.init();
+}
+
+/// Mở clock của --reference-clock (nếu có) và cài làm clock tham chiếu của process
+pub fn install_reference_clock(args: &Args) -> Result<()> {
+    if let Some(spec) = &args.reference_clock {
+        let clock = spec.open()?;
+        say!("🕰 Reference clock: {} (send/receive stamped as *_ref_ns)", clock.name());
+        let _ = REFERENCE_CLOCK.set(clock);
+    }
+    Ok(())
+}
+
+/// Một lần chạy test hoàn chỉnh theo `args`, trả về khi mọi lệnh xong hoặc bị dừng
+pub async fn run(args: Arc<Args>) -> Result<()> {
+    if let Some(filter) = &args.list_symbols {
+        return list_symbols(args.market, filter).await;
+    }
+
+    let gate_api_key = env::var("GATEIO_API_KEY")
+        .map_err(|_| anyhow::anyhow!("GATEIO_API_KEY not found in environment"))?;
+    let gate_api_secret = load_api_secret(&args)?;
+
+    let account = Arc::new(GateIOAccount::new(
+        gate_api_key,
+        gate_api_secret,
+        "GateIOAccount".to_string(),
+        args.clone(),
+    )?);
+    run_with_account(account).await
+}
+
+/// Như [`run`] nhưng với account có sẵn (credentials không lấy từ env); sau khi
+/// run xong, [`GateIOAccount::latency_stats`] vẫn đọc được
+pub async fn run_with_account(account: Arc<GateIOAccount>) -> Result<()> {
+    let args = account.args.clone();
+    say!("🌐 Starting Gate.io latency test for {}...", args.pairs().join(", "));
+    say!("📋 Test plan:");
+    if args.single_connection {
+        say!("   1. Subscribe orderbook on trading connection #0 (single connection)");
+    } else {
+        say!("   1. Connect to Gate.io orderbook WebSocket");
+    }
+    match args.auth_mode {
+        AuthMode::Login => say!("   2. Authenticate trading WebSocket"),
+        AuthMode::PerMessage => say!("   2. Connect trading WebSocket (per-message signed orders, no login)"),
+    }
+    say!("   3. Wait for orderbook data");
+    match args.trigger_after_ticks {
+        Some(n) => say!("   4. Wait for {} orderbook updates", n),
+        None => say!("   4. Wait {} seconds", args.wait_seconds),
+    }
+    if args.account_mode == AccountMode::Unified {
+        say!("   🏦 Account mode: unified{}", if args.market == Market::Spot { " (spot orders carry account=unified)" } else { "" });
+    }
+    if let Some(dist) = args.inter_order {
+        say!("   ⏲ Inter-order spacing: {}", dist);
+    }
+    if !args.inject.is_empty() {
+        say!("   💉 Failure injection active: {:?}", args.inject);
+    }
+    if args.chain {
+        say!("   🏓 Chain: next order after the previous one is finalized (+{:?})", args.chain_delay.unwrap_or_default());
+    }
+    say!("   🧭 Mode: {:?}{}", args.mode, if args.exits_when_done() { " (exit when all orders are answered)" } else { "" });
+    match &args.scenario {
+        Some(scenario) => say!("   5. Run {} scenario step(s) per pair on {} connection(s)", scenario.steps.len(), args.connections),
+        None => match args.target_notional {
+            Some(notional) => say!("   5. Place {} BUY order(s) of ~{} notional per pair on {} connection(s)", args.order_count(), notional, args.connections),
+            None => say!("   5. Place {} BUY order(s) of {} per pair on {} connection(s)", args.order_count(), SO_COIN_DANH, args.connections),
+        },
+    }
+    say!("   6. Measure latency for each response");
+    say!("   🎲 Random seed: {} (replay with --seed {})", account.seed, account.seed);
+    say!(
+        "   ⚙️ WebSocket limits: max message {} bytes, max frame {} bytes, write buffer {} bytes",
+        args.ws_max_message_size,
+        args.ws_max_frame_size,
+        args.ws_write_buffer_size
+    );
+    say!("   7. Show timing: Đặt lệnh → Response 1 and Response 2");
+
+    // Lifecycle events của các trading connection; consumer mặc định chỉ log lại
+    // và theo dõi prepare phase (connect + auth của mọi connection)
+    let (events_tx, mut events_rx) = mpsc::unbounded_channel::<ConnEvent>();
+    let (warm_tx, warm_rx) = watch::channel(false);
+    let started = Instant::now();
+    let total_connections = args.connections as usize;
+    let warm_deadline = (args.warm_timeout > 0).then(|| tokio::time::Instant::now() + Duration::from_secs(args.warm_timeout));
+    tokio::spawn(async move {
+        let mut authenticated = HashSet::new();
+        let mut prepared = false;
+        // --warm-timeout hết hạn: fire trên các connection đã sẵn sàng thay vì chờ mãi
+        let mut quorum = false;
+        loop {
+            let timeout = async {
+                match warm_deadline.filter(|_| !prepared && !quorum) {
+                    Some(deadline) => tokio::time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            let event = tokio::select! {
+                event = events_rx.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = timeout => {
+                    quorum = true;
+                    let left_out: Vec<String> =
+                        (0..total_connections).filter(|c| !authenticated.contains(c)).map(|c| format!("#{}", c)).collect();
+                    say!(
+                        "⌛ Prepare phase: {}/{} connection(s) authenticated after {}s, firing without {}",
+                        authenticated.len(),
+                        total_connections,
+                        started.elapsed().as_secs(),
+                        left_out.join(", ")
+                    );
+                    warm_tx.send_replace(!authenticated.is_empty());
+                    continue;
+                }
+            };
+            info!(
+                "[conn #{}] {:?} (+{:.3}s)",
+                event.conn,
+                event.state,
+                event.at.duration_since(started).as_secs_f64()
+            );
+            match event.state {
+                ConnState::Authenticated => {
+                    authenticated.insert(event.conn);
+                }
+                ConnState::Disconnected => {
+                    authenticated.remove(&event.conn);
+                }
+                _ => {}
+            }
+
+            let all_ready = authenticated.len() == total_connections;
+            let warm = all_ready || (quorum && !authenticated.is_empty());
+            if all_ready && !prepared {
+                prepared = true;
+                say!(
+                    "🔥 Prepare phase done: {} connection(s) connected + authenticated in {:.2} ms",
+                    total_connections,
+                    event.at.duration_since(started).as_secs_f64() * 1000.0
+                );
+            }
+            warm_tx.send_replace(warm);
+        }
+    });
+
+    if args.rest_check {
+        account.check_rest_credentials().await?;
+    }
+
+    let precisions = validate_instruments(args.market, &args.pairs()).await?;
+    account.precisions.lock().unwrap().extend(precisions);
+
+    let managers: Vec<Arc<ConnectionManager>> = (0..args.connections as usize)
+        .map(|index| Arc::new(ConnectionManager::with_events(index, account.clone(), events_tx.clone())))
+        .collect();
+
+    // Start both tasks concurrently
+    let trading_task = futures_util::future::try_join_all(managers.iter().cloned().map(supervise_trading));
+    let book_log = match &args.book_log {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!("Failed to create book log {}", path.display()))?;
+            Some(Arc::new(Mutex::new(BufWriter::new(file))))
+        }
+        None => None,
+    };
+    let orderbook_task = supervise_orderbook(managers.clone(), warm_rx, args.clone(), book_log.clone());
+
+    tokio::pin!(trading_task);
+
+    let sweeper = {
+        let account = account.clone();
+        let timeout = Duration::from_secs(args.order_timeout);
+        let deadline = args.order_deadline;
+        // Deadline ngắn cần quét dày hơn để timeout không bị trễ cả giây
+        let tick = deadline.map_or(Duration::from_secs(1), |d| (d / 5).clamp(Duration::from_millis(10), Duration::from_secs(1)));
+        tokio::spawn(async move {
+            loop {
+                sleep(tick).await;
+                if let Some(deadline) = deadline {
+                    account.expire_unacked(deadline);
+                }
+                account.expire_pending("timeout", |_, p| p.sent_time.elapsed() >= timeout);
+            }
+        })
+    };
+
+    // Mọi cặp đã fire xong và không còn lệnh nào chờ phản hồi
+    let orders_done = async {
+        let pairs = args.pairs().len();
+        while account.fire_phases_done.load(Ordering::Relaxed) < pairs || account.pending_count() > 0 {
+            sleep(Duration::from_millis(20)).await;
+        }
+    };
+
+    let mut outcome = Ok(());
+    let mut trading_alive = true;
+    tokio::select! {
+        result = &mut trading_task => {
+            trading_alive = false;
+            if let Err(e) = result {
+                error!("Trading WebSocket error: {}", e);
+                outcome = Err(e);
+            }
+        }
+        result = orderbook_task => {
+            if let Err(e) = result {
+                error!("Orderbook WebSocket error: {}", e);
+            }
+        }
+        _ = tokio::signal::ctrl_c() => {
+            say!("\n🛑 Shutting down...");
+        }
+        _ = orders_done, if args.exits_when_done() => {
+            say!("\n🏁 All orders finished, shutting down ({:?} mode)", args.mode);
+        }
+    }
+
+    // Drain: ngừng gửi lệnh mới nhưng vẫn đọc trading socket để nhận nốt ack của lệnh đã gửi
+    account.stop_new_orders();
+    let pending = account.pending_count();
+    if trading_alive && pending > 0 {
+        say!("⏳ Draining: waiting up to {}s for {} pending order(s) (Ctrl+C again to skip)...", args.drain_timeout, pending);
+        let drained = async {
+            while account.pending_count() > 0 {
+                sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::select! {
+            result = &mut trading_task => {
+                if let Err(e) = result {
+                    error!("Trading WebSocket error: {}", e);
+                    outcome = Err(e);
+                }
+            }
+            _ = tokio::time::timeout(Duration::from_secs(args.drain_timeout), drained) => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+        match account.pending_count() {
+            0 => say!("✅ Drain complete: all pending orders answered"),
+            remaining => say!("⚠️ Drain window expired with {} order(s) still pending", remaining),
+        }
+    }
+
+    sweeper.abort();
+    account.latency_stats.lock().unwrap().print_summary(managers.len(), args.dp());
+    account.ops.lock().unwrap().print();
+    if let Some(max_ms) = args.max_p95_ms {
+        let gate = account.latency_stats.lock().unwrap().check_p95(args.p95_target, max_ms, args.dp());
+        if outcome.is_ok() {
+            outcome = gate;
+        }
+    }
+    account.flush_outputs();
+    if let Some(book_log) = book_log {
+        if let Err(e) = book_log.lock().unwrap().flush() {
+            error!("Failed to flush book log: {}", e);
+        }
+    }
+
+    outcome
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_account(extra: &[&str]) -> Arc<GateIOAccount> {
+        let args = Args::try_parse_from(["gateio-latency-test"].iter().chain(extra)).unwrap();
+        Arc::new(GateIOAccount::new("key".to_string(), "secret".to_string(), "test".to_string(), Arc::new(args)).unwrap())
+    }
+
+    /// Sink giả thay cho WebSocket sender, giữ lại mọi message đã gửi
+    fn test_sink() -> futures_util::sink::SinkMapErr<Vec<Message>, fn(std::convert::Infallible) -> tungstenite::Error> {
+        Vec::new().sink_map_err(|never| match never {})
+    }
+
+    /// Gửi một lệnh BUY vào sink giả, trả về req_id trong payload
+    async fn place_test_order(account: &GateIOAccount) -> String {
+        let mut sink = test_sink();
+        account.place_order(&mut sink, 0, "BUY", "BTC_USDT", 0.001, 50000.0, "limit", "gtc").await.unwrap();
+        let Some(Message::Text(text)) = sink.get_ref().last() else { panic!("no order sent") };
+        let sent: Value = serde_json::from_str(text).unwrap();
+        sent["payload"]["req_id"].as_str().unwrap().to_string()
+    }
+
+    fn order_frame(channel: &str, req_id: &str, status: &str, ack: bool) -> String {
+        serde_json::json!({
+            "header": { "channel": channel, "event": "api", "status": status, "request_id": req_id },
+            "data": { "result": { "id": "1", "currency_pair": "BTC_USDT" } },
+            "ack": ack,
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn pong_records_round_trip_of_the_last_ping() {
+        let account = test_account(&[]);
+        let conn = ConnectionManager::new(0, account.clone());
+        let pong = r#"{"time":1700000000,"channel":"spot.pong","event":"","result":null}"#;
+        conn.handle_frame(pong).unwrap();
+        *conn.ping_sent.lock().unwrap() = Some(Instant::now());
+        conn.handle_frame(pong).unwrap();
+        let ops = account.ops.lock().unwrap();
+        assert_eq!(ops.pongs, 2);
+        assert_eq!(ops.ping_rtt.len(), 1);
+        assert!(conn.ping_sent.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn finalized_order_leaves_no_client_text() {
+        let account = test_account(&[]);
+        let conn = ConnectionManager::new(0, account.clone());
+        let req_id = place_test_order(&account).await;
+        assert_eq!(account.text_index.lock().unwrap().len(), 1);
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "201", false)).unwrap();
+        assert!(account.text_index.lock().unwrap().is_empty());
+
+        let req_id = place_test_order(&account).await;
+        assert_eq!(account.expire_pending("test", |id, _| id == req_id), 1);
+        assert!(account.text_index.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_connection_reports_the_order_as_not_placed() {
+        for extra in [&[][..], &["--queue-until-auth-ms", "1"][..]] {
+            let conn = ConnectionManager::new(0, test_account(extra));
+            let result = conn.create_order("BUY", "BTC_USDT", 0.001, 50000.0, "limit", "gtc", None, None).await;
+            assert!(matches!(result, Err(Error::Auth(_))), "{:?}", result);
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_workers_keep_frame_order() {
+        let messages = (0..50).map(|i| (0, Message::Text(format!(r#"{{"n":{}}}"#, i))));
+        let feed = futures_util::stream::iter(messages).map(|(feed, message)| (feed, Ok(message)));
+        let frames: Vec<_> = book_frames(feed, Some(4)).collect().await;
+        let order: Vec<_> = frames.iter().map(|(_, frame)| frame.as_ref().unwrap().data.as_ref().unwrap()["n"].as_u64().unwrap()).collect();
+        assert_eq!(order, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn order_number_serializes_text_verbatim() {
+        let numeric = |text: &str| serde_json::to_string(&OrderNumber::new(text.to_string(), true)).unwrap();
+        assert_eq!(numeric("0.00000123"), "0.00000123");
+        assert_eq!(numeric("0.0000001"), "0.0000001");
+        assert_eq!(numeric("123456789012345678901.123456789"), "123456789012345678901.123456789");
+        assert_eq!(numeric("50"), "50");
+        assert_eq!(serde_json::to_string(&OrderNumber::new("0.0000001".to_string(), false)).unwrap(), "\"0.0000001\"");
+        assert!(serde_json::to_string(&OrderNumber::new("abc".to_string(), true)).is_err());
+    }
+
+    #[test]
+    fn finalized_orders_forget_the_oldest() {
+        let mut finalized = FinalizedOrders::default();
+        for i in 0..=FINALIZED_MEMORY {
+            finalized.insert(i.to_string());
+        }
+        finalized.insert(FINALIZED_MEMORY.to_string());
+        assert!(!finalized.contains("0"));
+        assert!(finalized.contains("1"));
+        assert!(finalized.contains(&FINALIZED_MEMORY.to_string()));
+        assert_eq!(finalized.ids.len(), FINALIZED_MEMORY);
+        assert_eq!(finalized.order.len(), FINALIZED_MEMORY);
+    }
+
+    #[test]
+    fn ramp_moves_toward_end_and_stops_there() {
+        let started_ago = |secs| Some(Instant::now() - Duration::from_secs(secs));
+        let up = RateLimiter::new(0.0, Some("1,5,1,1".parse().unwrap()));
+        assert_eq!(up.rate_at(started_ago(2)), 3.0);
+        assert_eq!(up.rate_at(started_ago(60)), 5.0);
+        let down = RateLimiter::new(0.0, Some("10,4,2,1".parse().unwrap()));
+        assert_eq!(down.rate_at(None), 10.0);
+        assert_eq!(down.rate_at(started_ago(2)), 6.0);
+        assert_eq!(down.rate_at(started_ago(60)), 4.0);
+    }
+
+    // RFC 4231 test case 2
+    const RFC4231_KEY: &str = "Jefe";
+    const RFC4231_DATA: &str = "what do ya want for nothing?";
+
+    #[test]
+    fn hmac_signers_match_rfc4231() {
+        let sha256 = build_signer(KeyType::Hmac, HmacDigest::Sha256, RFC4231_KEY).unwrap();
+        assert_eq!(sha256.name(), "hmac-sha256");
+        assert_eq!(sha256.sign_message(RFC4231_DATA), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        let sha512 = build_signer(KeyType::Hmac, HmacDigest::Sha512, RFC4231_KEY).unwrap();
+        assert_eq!(sha512.name(), "hmac-sha512");
+        assert_eq!(
+            sha512.sign_message(RFC4231_DATA),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+    }
+
+    #[test]
+    fn hmac_signers_sign_gateio_ws_requests() {
+        let param = r#"{"text":"t-1","currency_pair":"BTC_USDT"}"#;
+        let sha512 = build_signer(KeyType::Hmac, HmacDigest::Sha512, "secret").unwrap();
+        assert_eq!(
+            sha512.sign("spot.order_place", param, 1700000000),
+            "ef79b9607ee0d40ecfcef8f70b781d9cc057b6c7de976139a6ee1c4e9eb3e094a5724aca9b6e4b863049aaacfb44e172777ec942742fa8e4bd88e0506978ebb7"
+        );
+        let sha256 = build_signer(KeyType::Hmac, HmacDigest::Sha256, "secret").unwrap();
+        assert_eq!(
+            sha256.sign("spot.order_place", param, 1700000000),
+            "68c96df7e13dab22c070bab207d1b89278e1ecdfd646bab31250800191c16f1f"
+        );
+    }
+
+    // Ví dụ GET/POST trong tài liệu APIv4 của Gate.io (SHA512 của body rỗng là hằng số)
+    const EMPTY_BODY_SHA512: &str =
+        "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e";
+
+    #[test]
+    fn rest_sign_string_follows_gateio_v4() {
+        let query = "contract=BTC_USD&status=finished&limit=50";
+        assert_eq!(
+            rest_sign_string("get", "/api/v4/futures/orders", query, "", 1541993715),
+            format!("GET\n/api/v4/futures/orders\n{}\n{}\n1541993715", query, EMPTY_BODY_SHA512)
+        );
+        let body = r#"{"contract":"BTC_USD","size":100,"price":"6800","tif":"gtc"}"#;
+        assert_eq!(
+            rest_sign_string("POST", "/api/v4/futures/orders", "", body, 1541993715),
+            "POST\n/api/v4/futures/orders\n\n2a5419cb37f36ab3e6edb18fe167b62e966b8a9153eabcb7393f09a3067998b7ef6096e25b497556c6693e98f90de1a1b4049192849976b1d52e41a912ae019e\n1541993715"
+        );
+    }
+
+    #[test]
+    fn rest_signatures_match_known_vectors() {
+        let signer = build_signer(KeyType::Hmac, HmacDigest::Sha512, "secret").unwrap();
+        assert_eq!(
+            signer.sign_rest("GET", "/api/v4/futures/orders", "contract=BTC_USD&status=finished&limit=50", "", 1541993715),
+            "55f84ea195d6fe57ce62464daaa7c3c02fa9d1dde954e4c898289c9a2407a3d6fb3faf24deff16790d726b66ac9f74526668b13bd01029199cc4fcc522418b8a"
+        );
+        let body = r#"{"contract":"BTC_USD","size":100,"price":"6800","tif":"gtc"}"#;
+        assert_eq!(
+            signer.sign_rest("POST", "/api/v4/futures/orders", "", body, 1541993715),
+            "84482f7f5be77fb90333473ffb3809ab3cb1757507c6b90e0b9a2becf6cbd1c972576dda3057c0f0b053956b82284a0bdb5995e34d31479c8f59392d417a94a1"
+        );
+    }
+
+    #[test]
+    fn rest_auth_headers_carry_key_and_matching_signature() {
+        let account = test_account(&[]);
+        let auth = account.rest_auth("GET", "/api/v4/spot/accounts", "currency=USDT", "");
+        assert_eq!(auth.key, "key");
+        let ts: u64 = auth.timestamp.parse().unwrap();
+        assert_eq!(auth.sign, account.signer.sign_rest("GET", "/api/v4/spot/accounts", "currency=USDT", "", ts));
+    }
+
+    #[test]
+    fn ws_sign_string_is_canonical() {
+        assert_eq!(sign_string("spot.order_place", r#"{"a":1}"#, 1700000000), "api\nspot.order_place\n{\"a\":1}\n1700000000");
+        let signer = build_signer(KeyType::Hmac, HmacDigest::Sha512, "secret").unwrap();
+        let message = sign_string("spot.login", "", 1700000000);
+        assert_eq!(signer.sign("spot.login", "", 1700000000), signer.sign_message(&message));
+    }
+
+    #[test]
+    fn ed25519_signer_matches_rfc8032() {
+        use base64::Engine;
+        let decode = |signature: String| hex::encode(base64::engine::general_purpose::STANDARD.decode(signature).unwrap());
+        let test1 = build_signer(
+            KeyType::Ed25519,
+            HmacDigest::Sha512,
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        )
+        .unwrap();
+        assert_eq!(test1.name(), "ed25519");
+        assert_eq!(
+            decode(test1.sign_message("")),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        let test2 = build_signer(
+            KeyType::Ed25519,
+            HmacDigest::Sha512,
+            "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        )
+        .unwrap();
+        assert_eq!(
+            decode(test2.sign_message("r")),
+            "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00"
+        );
+    }
+
+    #[test]
+    fn ed25519_key_formats_are_validated() {
+        assert!(matches!(build_signer(KeyType::Ed25519, HmacDigest::Sha512, "not a key"), Err(Error::Signature(_))));
+        assert!(matches!(build_signer(KeyType::Ed25519, HmacDigest::Sha512, "abcd"), Err(Error::Signature(_))));
+    }
+
+    #[test]
+    fn step_rounding_modes() {
+        let tick = Step::from_decimals(5);
+        assert_eq!(tick.format(0.123456, RoundMode::Nearest), "0.12346");
+        assert_eq!(tick.format(0.123454, RoundMode::Nearest), "0.12345");
+        assert_eq!(tick.format(0.123451, RoundMode::Up), "0.12346");
+        assert_eq!(tick.format(0.123459, RoundMode::Down), "0.12345");
+        // Sai số float không đẩy giá đã đúng tick sang tick kế bên
+        assert_eq!(tick.format(0.02789, RoundMode::Down), "0.02789");
+        assert_eq!(tick.format(0.02789, RoundMode::Up), "0.02789");
+    }
+
+    #[test]
+    fn step_rounding_ties() {
+        let tick = Step::parse("0.5").unwrap();
+        assert_eq!(tick.decimals, 1);
+        // Đúng giữa hai tick: nearest làm tròn ra xa 0, up/down theo chiều của mode
+        assert_eq!(tick.format(1.25, RoundMode::Nearest), "1.5");
+        assert_eq!(tick.format(1.25, RoundMode::Up), "1.5");
+        assert_eq!(tick.format(1.25, RoundMode::Down), "1.0");
+        let futures_tick = Step::parse("0.05").unwrap();
+        assert_eq!(futures_tick.format(100.025, RoundMode::Down), "100.00");
+        assert_eq!(futures_tick.format(100.025, RoundMode::Up), "100.05");
+        assert_eq!(futures_tick.format(100.05, RoundMode::Down), "100.05");
+    }
+
+    #[test]
+    fn format_decimal_is_stable() {
+        assert_eq!(format_decimal(50.0), "50");
+        assert_eq!(format_decimal(0.1 + 0.2), "0.3");
+        assert_eq!(format_decimal(0.00000123), "0.00000123");
+        assert_eq!(format_decimal(-0.0), "0");
+        assert_eq!(format_decimal(1e-12), "0");
+    }
+
+    #[test]
+    fn default_rounding_is_conservative_per_side() {
+        let account = test_account(&[]);
+        account.precisions.lock().unwrap().insert(
+            "BTC_USDT".to_string(),
+            Precision { price: Some(Step::from_decimals(1)), amount: Some(Step::from_decimals(3)), ..Default::default() },
+        );
+        assert_eq!(account.format_order_values("BTC_USDT", Side::Buy, 100.07, 0.0019), ("100.0".to_string(), "0.001".to_string()));
+        assert_eq!(account.format_order_values("BTC_USDT", Side::Sell, 100.03, 0.0019), ("100.1".to_string(), "0.001".to_string()));
+        let nearest = test_account(&["--round-mode", "nearest"]);
+        nearest.precisions.lock().unwrap().insert("BTC_USDT".to_string(), Precision { price: Some(Step::from_decimals(1)), ..Default::default() });
+        assert_eq!(nearest.format_order_values("BTC_USDT", Side::Buy, 100.07, 1.0).0, "100.1");
+        // Chưa biết tick: giữ nguyên giá trị, format ổn định
+        assert_eq!(account.format_order_values("ETH_USDT", Side::Buy, 100.07, 0.5), ("100.07".to_string(), "0.5".to_string()));
+    }
+
+    #[test]
+    fn response_result_reads_both_shapes() {
+        let nested: Value = serde_json::from_str(r#"{"data":{"result":{"id":"1"}}}"#).unwrap();
+        assert_eq!(response_result(&nested).unwrap()["id"], "1");
+        let top_level: Value = serde_json::from_str(r#"{"result":{"id":"2"}}"#).unwrap();
+        assert_eq!(response_result(&top_level).unwrap()["id"], "2");
+        let array: Value = serde_json::from_str(r#"{"data":{"result":[{"id":"3"},{"id":"4"}]}}"#).unwrap();
+        assert_eq!(response_result(&array).unwrap().as_array().unwrap().len(), 2);
+        let top_array: Value = serde_json::from_str(r#"{"result":[{"id":"5"}]}"#).unwrap();
+        assert_eq!(response_result(&top_array).unwrap()[0]["id"], "5");
+        let both: Value = serde_json::from_str(r#"{"data":{"result":{"id":"6"}},"result":{"id":"7"}}"#).unwrap();
+        assert_eq!(response_result(&both).unwrap()["id"], "6");
+        let null_nested: Value = serde_json::from_str(r#"{"data":{"result":null},"result":{"id":"8"}}"#).unwrap();
+        assert_eq!(response_result(&null_nested).unwrap()["id"], "8");
+        let none: Value = serde_json::from_str(r#"{"data":{"errs":{"label":"X"}}}"#).unwrap();
+        assert!(response_result(&none).is_none());
+    }
+
+    /// Frame đã capture/lấy từ tài liệu WS v4 của Gate.io, trong tests/fixtures
+    fn fixture(name: &str) -> Value {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn envelope_of_v4_frames() {
+        let login = fixture("login_v4.json");
+        let envelope = parse_envelope(&login);
+        assert_eq!((envelope.channel, envelope.event, envelope.status), ("spot.login", "api", Some("200")));
+        assert_eq!(envelope.request_id, Some("request-1"));
+        assert_eq!(envelope.result.unwrap()["uid"], "110284739");
+        assert!(!envelope.ack);
+
+        let ack = fixture("order_place_ack_v4.json");
+        let envelope = parse_envelope(&ack);
+        assert_eq!((envelope.channel, envelope.status, envelope.request_id), ("spot.order_place", Some("200"), Some("request-2")));
+        assert!(envelope.ack);
+        assert_eq!(server_processing_ms(envelope.header), Some(0.09));
+
+        let result = fixture("order_place_result_v4.json");
+        let envelope = parse_envelope(&result);
+        assert!(!envelope.ack);
+        assert_eq!(envelope.result.unwrap()["text"], "t-my-custom-id");
+        assert_eq!(envelope.error_message, None);
+
+        let error = fixture("order_place_error_v4.json");
+        let envelope = parse_envelope(&error);
+        assert_eq!(envelope.status, Some("400"));
+        assert_eq!(envelope.error_label, Some("INVALID_PARAM_VALUE"));
+        assert_eq!(envelope.error_message, Some("Invalid amount"));
+        assert!(envelope.result.is_none());
+    }
+
+    #[test]
+    fn envelope_of_legacy_top_level_frames() {
+        let pong = fixture("pong_legacy.json");
+        let envelope = parse_envelope(&pong);
+        assert!(envelope.header.is_none());
+        assert_eq!((envelope.channel, envelope.event, envelope.status), ("spot.pong", "", None));
+        assert!(envelope.result.is_none());
+
+        let error = fixture("subscribe_error_legacy.json");
+        let envelope = parse_envelope(&error);
+        assert_eq!((envelope.channel, envelope.event), ("spot.orders", "subscribe"));
+        assert_eq!(envelope.error_message, Some("Invalid argument"));
+
+        let ticker = fixture("book_ticker_update.json");
+        let envelope = parse_envelope(&ticker);
+        assert_eq!((envelope.channel, envelope.event), ("spot.book_ticker", "update"));
+        assert_eq!(envelope.result.unwrap()["s"], "BTC_USDT");
+        assert_eq!(envelope.request_id, None);
+    }
+
+    #[tokio::test]
+    async fn ack_then_result_are_receipt_and_result_latency() {
+        let account = test_account(&[]);
+        let conn = ConnectionManager::new(0, account.clone());
+        let req_id = place_test_order(&account).await;
+
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "200", true)).unwrap();
+        // Ack lặp lại không tạo sample thứ hai
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "200", true)).unwrap();
+        assert_eq!(account.pending_count(), 1);
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "201", false)).unwrap();
+        assert_eq!(account.pending_count(), 0);
+
+        let stats = account.latency_stats();
+        let (receipt, result) = (stats.latencies(LatencyTarget::Receipt.response_num()), stats.latencies(LatencyTarget::Result.response_num()));
+        assert_eq!((receipt.len(), result.len()), (1, 1));
+        assert!(result[0] >= receipt[0]);
+        assert_eq!(response_label(1), "receipt");
+        assert_eq!(response_label(2), "result");
+        assert!(stats.check_p95(LatencyTarget::Receipt, 60_000.0, 3).is_ok());
+        assert!(stats.check_p95(LatencyTarget::Result, -1.0, 3).is_err());
+    }
+
+    #[tokio::test]
+    async fn result_before_ack_is_still_classified_by_content() {
+        let account = test_account(&[]);
+        let conn = ConnectionManager::new(0, account.clone());
+        let req_id = place_test_order(&account).await;
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "201", false)).unwrap();
+        // Lệnh đã finalize: ack tới muộn chỉ được log, không thành sample
+        conn.handle_frame(&order_frame("spot.order_place", &req_id, "200", true)).unwrap();
+        let stats = account.latency_stats();
+        assert_eq!((stats.latencies(1).len(), stats.latencies(2).len()), (0, 1));
+        assert!(stats.check_p95(LatencyTarget::Receipt, 60_000.0, 3).is_err());
+    }
+
+    fn book_state(placed: &[&str]) -> BookState {
+        BookState {
+            order_placed: Mutex::new(placed.iter().map(|p| p.to_string()).collect()),
+            commands: tokio::sync::Mutex::new(mpsc::unbounded_channel().1),
+            progress: Progress::new(),
+        }
+    }
+
+    #[test]
+    fn restart_keeps_placed_pairs_by_default() {
+        let state = book_state(&["BTC_USDT", "ALCH_USDT"]);
+        assert!(state.after_restart(false).is_empty());
+        let placed = state.order_placed.lock().unwrap();
+        assert!(placed.contains("BTC_USDT") && placed.contains("ALCH_USDT"));
+    }
+
+    #[test]
+    fn restart_reopens_pairs_with_reorder_on_reconnect() {
+        let state = book_state(&["BTC_USDT", "ALCH_USDT"]);
+        assert_eq!(state.after_restart(true), vec!["ALCH_USDT".to_string(), "BTC_USDT".to_string()]);
+        assert!(state.order_placed.lock().unwrap().is_empty());
+        assert!(state.after_restart(true).is_empty());
+        let args = Args::try_parse_from(["gateio-latency-test", "--reorder-on-reconnect"]).unwrap();
+        assert!(args.reorder_on_reconnect);
+    }
+
+    #[test]
+    fn truncate_str_never_splits_a_character() {
+        assert_eq!(truncate_str("abc", 10), "abc");
+        assert_eq!(truncate_str("abcdef", 3), "abc");
+        // "ệ" là 3 byte: cắt theo ký tự, không theo byte
+        let vietnamese = "Lệnh không hợp lệ";
+        assert_eq!(truncate_str(vietnamese, 2), "Lệ");
+        assert_eq!(truncate_str(vietnamese, 7), "Lệnh kh");
+        let emoji = "🚀🚀🚀 sent";
+        assert_eq!(truncate_str(emoji, 1), "🚀");
+        assert_eq!(truncate_str(emoji, 4), "🚀🚀🚀 ");
+        // Mọi độ dài cắt đều ra &str hợp lệ (không panic giữa byte của ký tự)
+        for max in 0..=vietnamese.chars().count() {
+            assert_eq!(truncate_str(vietnamese, max).chars().count(), max);
+        }
+        assert!(frame_context(&"ệ".repeat(300)).ends_with('…'));
+    }
+
+    #[test]
+    fn side_type_and_time_in_force_reject_invalid_input() {
+        assert_eq!("BUY".parse::<Side>().unwrap(), Side::Buy);
+        assert_eq!("sell".parse::<Side>().unwrap(), Side::Sell);
+        for side in ["bui", "", "long", "buy "] {
+            assert!(matches!(side.parse::<Side>(), Err(Error::Order(_))), "{:?}", side);
+        }
+        assert_eq!("Limit".parse::<OrderType>().unwrap(), OrderType::Limit);
+        for order_type in ["limt", "", "stop"] {
+            assert!(matches!(order_type.parse::<OrderType>(), Err(Error::Order(_))), "{:?}", order_type);
+        }
+        assert_eq!("IOC".parse::<TimeInForce>().unwrap(), TimeInForce::Ioc);
+        for tif in ["gtd", "", "day"] {
+            assert!(matches!(tif.parse::<TimeInForce>(), Err(Error::Order(_))), "{:?}", tif);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_order_params_are_rejected_before_sending() {
+        let account = test_account(&[]);
+        let mut sink = test_sink();
+        for (side, order_type, tif) in [("bui", "limit", "gtc"), ("buy", "limt", "gtc"), ("buy", "limit", "gtd")] {
+            let result = account.place_order(&mut sink, 0, side, "BTC_USDT", 1.0, 100.0, order_type, tif).await;
+            assert!(matches!(result, Err(Error::Order(_))), "{} {} {}", side, order_type, tif);
+        }
+        for (quantity, price) in [(0.0, 100.0), (-1.0, 100.0), (1.0, 0.0), (1.0, -100.0)] {
+            let result = account.place_order(&mut sink, 0, "buy", "BTC_USDT", quantity, price, "limit", "gtc").await;
+            assert!(matches!(result, Err(Error::Order(_))), "{} {}", quantity, price);
+        }
+        assert!(sink.get_ref().is_empty());
+        assert_eq!(account.pending_count(), 0);
+    }
+
+    #[test]
+    fn inject_requires_the_live_acknowledgement() {
+        let parse = |extra: &[&str]| Args::try_parse_from(["gateio-latency-test"].iter().chain(extra)).unwrap();
+        assert!(parse(&["--inject", "reject-rate:10"]).validate().is_err());
+        assert!(parse(&["--inject", "reject-rate:10", "--i-know-this-is-live"]).validate().is_ok());
+        assert!(Args::try_parse_from(["gateio-latency-test", "--i-know-this-is-live"]).is_err());
+    }
+
+    #[test]
+    fn mode_once_rejects_a_count() {
+        let parse = |extra: &[&str]| Args::try_parse_from(["gateio-latency-test"].iter().chain(extra)).unwrap();
+        assert!(parse(&["--mode", "once", "--count", "50"]).validate().is_err());
+        assert!(parse(&["--mode", "once"]).validate().is_ok());
+        assert!(parse(&["--mode", "bench", "--count", "50"]).validate().is_ok());
+    }
+
+    #[test]
+    fn lowercase_pairs_are_normalized_to_uppercase() {
+        assert_eq!(canonical_pair("alch_usdt").unwrap(), "ALCH_USDT");
+        let args = Args::try_parse_from(["gateio-latency-test", "--pairs", "alch_usdt,Alch_Btc,ALCH_USDT"]).unwrap();
+        assert_eq!(args.pairs(), vec!["ALCH_USDT".to_string(), "ALCH_BTC".to_string()]);
+        let default = Args::try_parse_from(["gateio-latency-test"]).unwrap();
+        assert_eq!(default.pairs(), vec![format!("{}_USDT", SYMBOL)]);
+    }
+
+    #[test]
+    fn canonical_pair_accepts_slash_hyphen_underscore() {
+        for input in ["btc/usdt", "BTC-USDT", "btc_usdt", "  Btc/Usdt\t", "BTC_USDT"] {
+            assert_eq!(canonical_pair(input).unwrap(), "BTC_USDT", "{:?}", input);
+        }
+        assert_eq!(canonical_pair("1inch-usdt").unwrap(), "1INCH_USDT");
+    }
+
+    #[test]
+    fn canonical_pair_rejects_malformed_input() {
+        for input in ["BTC//USDT", "BTC/", "/USDT", "BTC", "", "BTC_USDT_X", "BTC/USDT-X", "BTC USDT", "BTC _USDT", "BTC.USDT", "BTC_ÜSDT"] {
+            assert!(matches!(canonical_pair(input), Err(Error::Order(_))), "{:?}", input);
+        }
+    }
+
+    /// Thư mục tạm riêng cho từng test, xoá sạch trước khi dùng
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gateio-latency-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn rotating_writer_appends_and_writes_the_header_once() {
+        let path = temp_dir("append").join("latency.csv");
+        for row in ["1", "2"] {
+            let mut writer = RotatingWriter::create(&path, Some("h".to_string()), RotationPolicy::default()).unwrap();
+            writer.write_line(row).unwrap();
+            writer.flush().unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "h\n1\n2\n");
+    }
+
+    #[test]
+    fn rotation_continues_after_the_highest_existing_segment() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("latency.csv");
+        assert_eq!(RotatingWriter::last_segment(&path), 0);
+        std::fs::write(dir.join("latency.csv.1.gz"), "").unwrap();
+        std::fs::write(dir.join("latency.csv.3.gz"), "").unwrap();
+        std::fs::write(dir.join("latency.csv.4"), "").unwrap();
+        std::fs::write(dir.join("latency.csv.x.gz"), "").unwrap();
+        std::fs::write(dir.join("other.csv.9.gz"), "").unwrap();
+        assert_eq!(RotatingWriter::last_segment(&path), 4);
+
+        let mut writer = RotatingWriter::create(&path, Some("h".to_string()), RotationPolicy { max_bytes: Some(1), max_age: None }).unwrap();
+        writer.write_line("1").unwrap();
+        writer.write_line("2").unwrap();
+        assert_eq!(writer.segment, 5);
+        assert_eq!(std::fs::read_to_string(dir.join("latency.csv.3.gz")).unwrap(), "");
+    }
+
+    // Nén raw deflate + sync flush như server permessage-deflate, bỏ trailer 00 00 ff ff
+    fn deflate_message(compress: &mut flate2::Compress, text: &str) -> Vec<u8> {
+        let mut out = Vec::with_capacity(text.len() + 64);
+        compress.compress_vec(text.as_bytes(), &mut out, flate2::FlushCompress::Sync).unwrap();
+        assert!(out.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+        out.truncate(out.len() - 4);
+        out
+    }
+
+    #[test]
+    fn inflater_rewrites_compressed_frames_after_the_handshake() {
+        let handshake = b"HTTP/1.1 101 Switching Protocols\r\nSec-WebSocket-Extensions: permessage-deflate\r\n\r\n";
+        let first = r#"{"channel":"spot.book_ticker","result":{"s":"BTC_USDT"}}"#;
+        let second = r#"{"channel":"spot.book_ticker","result":{"s":"BTC_USDT","b":"1"}}"#;
+        let mut compress = flate2::Compress::new(flate2::Compression::default(), false);
+        let one = deflate_message(&mut compress, first);
+        // Message thứ 2 tham chiếu context của message 1 (context takeover)
+        let two = deflate_message(&mut compress, second);
+
+        let mut wire = handshake.to_vec();
+        wire.extend_from_slice(&[0xc1, one.len() as u8]);
+        wire.extend_from_slice(&one);
+        // Ping chen giữa, đi thẳng
+        wire.extend_from_slice(&[0x89, 0x00]);
+        // Message 2 phân mảnh: RSV1 chỉ ở frame đầu
+        let (head, tail) = two.split_at(two.len() / 2);
+        wire.extend_from_slice(&[0x41, head.len() as u8]);
+        wire.extend_from_slice(head);
+        wire.extend_from_slice(&[0x80, tail.len() as u8]);
+        wire.extend_from_slice(tail);
+
+        let mut inflater = Inflater::new(1 << 20);
+        // Từng byte một để kiểm tra frame/handshake bị cắt giữa các lần đọc
+        for b in &wire {
+            inflater.feed(std::slice::from_ref(b)).unwrap();
+        }
+        let mut expected = handshake.to_vec();
+        expected.extend_from_slice(&[0x81, first.len() as u8]);
+        expected.extend_from_slice(first.as_bytes());
+        expected.extend_from_slice(&[0x89, 0x00]);
+        expected.extend_from_slice(&[0x81, second.len() as u8]);
+        expected.extend_from_slice(second.as_bytes());
+        assert_eq!(inflater.out, expected);
+        assert_eq!(inflater.inflated, 2);
+        assert_eq!(inflater.inflated_bytes, (first.len() + second.len()) as u64);
+    }
+
+    #[test]
+    fn inflater_passes_uncompressed_frames_and_rejects_oversized_messages() {
+        let mut inflater = Inflater::new(16);
+        inflater.feed(b"HTTP/1.1 101 OK\r\n\r\n\x81\x02hi").unwrap();
+        assert_eq!(inflater.out, b"HTTP/1.1 101 OK\r\n\r\n\x81\x02hi");
+        assert_eq!(inflater.inflated, 0);
+
+        let mut compress = flate2::Compress::new(flate2::Compression::default(), false);
+        let payload = deflate_message(&mut compress, &"x".repeat(64));
+        let mut frame = vec![0xc1, payload.len() as u8];
+        frame.extend_from_slice(&payload);
+        assert_eq!(inflater.feed(&frame).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn inflater_rejects_an_oversized_frame_header_before_buffering() {
+        let mut inflater = Inflater::new(1 << 20);
+        inflater.feed(b"HTTP/1.1 101 OK\r\n\r\n").unwrap();
+        // Header 127 + độ dài 64-bit khổng lồ: lỗi ngay, không chờ payload
+        let mut header = vec![0xc1, 0x7f];
+        header.extend_from_slice(&u64::MAX.to_be_bytes());
+        assert_eq!(inflater.feed(&header).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        let mut exact = vec![0x81, 0x7e];
+        exact.extend_from_slice(&(1u16 << 15).to_be_bytes());
+        assert_eq!(Inflater::frame_len(&exact, 1 << 15).unwrap(), Some((4, 4 + (1 << 15))));
+        assert!(Inflater::frame_len(&exact, (1 << 15) - 1).is_err());
+        assert_eq!(Inflater::frame_len(&exact[..3], 1 << 15).unwrap(), None);
+    }
+
+    #[test]
+    fn imbalance_gate_reads_the_current_book() {
+        let share_prices = Mutex::new(HashMap::new());
+        assert_eq!(imbalance_gate(&share_prices, "BTC_USDT", 0.2), Err("no depth data".to_string()));
+
+        let book = SharePrice { bids: vec![(100.0, 3.0)], asks: vec![(101.0, 1.0)], ..Default::default() };
+        share_prices.lock().unwrap().insert("BTC_USDT".to_string(), book);
+        assert_eq!(imbalance_gate(&share_prices, "BTC_USDT", 0.2), Ok(0.5));
+
+        // Book đổi sau lúc quyết định: lần kiểm tra ngay trước khi gửi thấy giá trị mới
+        share_prices.lock().unwrap().get_mut("BTC_USDT").unwrap().asks = vec![(101.0, 3.0)];
+        assert_eq!(imbalance_gate(&share_prices, "BTC_USDT", 0.2), Err("book imbalance 0.0000 <= 0.2000".to_string()));
+    }
+
+    #[tokio::test]
+    async fn every_amend_ack_is_measured_by_its_request_id() {
+        let account = test_account(&[]);
+        let conn = ConnectionManager::new(0, account.clone());
+        let mut sink = test_sink();
+        for price in [49000.0, 48000.0, 47000.0, 46000.0] {
+            account.amend_order(&mut sink, 0, "1", "BTC_USDT", Some(price), None).await.unwrap();
+        }
+        let req_ids: Vec<String> = sink
+            .get_ref()
+            .iter()
+            .map(|m| {
+                let Message::Text(text) = m else { panic!("not text") };
+                serde_json::from_str::<Value>(text).unwrap()["payload"]["req_id"].as_str().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(req_ids.len(), 4);
+
+        // Result không tính, chỉ ack của từng amend
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[1], "200", true)).unwrap();
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[0], "200", true)).unwrap();
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[0], "200", false)).unwrap();
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[1], "200", false)).unwrap();
+        // Amend bị từ chối (ack lỗi hoặc lỗi không kèm ack): không vào sample, tính là failure
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[2], "400", true)).unwrap();
+        conn.handle_frame(&order_frame("spot.order_amend", &req_ids[3], "400", false)).unwrap();
+        assert_eq!(account.latency_stats.lock().unwrap().amend_samples.len(), 2);
+        assert_eq!(account.ops.lock().unwrap().failures.values().sum::<u64>(), 2);
+        assert!(account.sent_time_map.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn quote_age_applies_the_measured_clock_offset() {
+        let account = test_account(&[]);
+        // Đồng hồ server chạy trước local 5s: quote vừa phát có t = local + 5000
+        account.clock_offset.store(5, Ordering::Relaxed);
+        let server_t = account.get_ts_ms() + 5000;
+        let age = account.quote_age_ms(server_t);
+        assert!((0..1000).contains(&age), "age {} ms", age);
+    }
+}
@@ -0,0 +1,73 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+
+use clap::Parser;
+use futures_util::SinkExt;
+use gateio_latency_test::{build_signer, sign_string, Args, ConnectionManager, GateIOAccount, HmacDigest, KeyType};
+use serde_json::{json, Value};
+use tokio_tungstenite::tungstenite::{self, Message};
+
+fn account(extra: &[&str]) -> Arc<GateIOAccount> {
+    let args = Args::try_parse_from(["gateio-latency-test"].iter().chain(extra)).unwrap();
+    args.validate().unwrap();
+    Arc::new(GateIOAccount::new("key".to_string(), "secret".to_string(), "test".to_string(), Arc::new(args)).unwrap())
+}
+
+fn test_sink() -> futures_util::sink::SinkMapErr<Vec<Message>, fn(Infallible) -> tungstenite::Error> {
+    Vec::new().sink_map_err(|never| match never {})
+}
+
+fn frame(channel: &str, status: &str, request_id: &str, ack: bool, result: Value) -> String {
+    json!({
+        "header": { "channel": channel, "event": "api", "status": status, "request_id": request_id },
+        "data": { "result": result },
+        "ack": ack,
+    })
+    .to_string()
+}
+
+#[test]
+fn signer_signs_the_canonical_string() {
+    let signer = build_signer(KeyType::Hmac, HmacDigest::Sha512, "secret").unwrap();
+    let message = sign_string("spot.login", "", 1700000000);
+    assert_eq!(message, "api\nspot.login\n\n1700000000");
+    assert_eq!(signer.sign("spot.login", "", 1700000000), signer.sign_message(&message));
+    assert_eq!(signer.sign_message(&message).len(), 128);
+}
+
+#[tokio::test]
+async fn order_round_trip_records_latency() {
+    let account = account(&["--pairs", "btc/usdt"]);
+    let conn = ConnectionManager::new(0, account.clone());
+
+    conn.handle_frame(&frame("spot.login", "200", "login", false, json!({ "uid": "1" }))).unwrap();
+    assert!(conn.is_authenticated());
+
+    let mut sink = test_sink();
+    account.place_order(&mut sink, 0, "BUY", "BTC_USDT", 0.001, 50000.0, "limit", "gtc").await.unwrap();
+    let sent: Vec<Value> = sink
+        .get_ref()
+        .iter()
+        .map(|m| match m {
+            Message::Text(text) => serde_json::from_str(text).unwrap(),
+            other => panic!("unexpected frame {:?}", other),
+        })
+        .collect();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0]["channel"], "spot.order_place");
+    assert_eq!(sent[0]["payload"]["req_param"]["currency_pair"], "BTC_USDT");
+    assert_eq!(sent[0]["payload"]["req_param"]["price"], "50000");
+    let req_id = sent[0]["payload"]["req_id"].as_str().unwrap();
+    assert_eq!(account.pending_count(), 1);
+
+    conn.handle_frame(&frame("spot.order_place", "200", req_id, true, json!({ "req_id": req_id }))).unwrap();
+    assert_eq!(account.pending_count(), 1);
+    conn.handle_frame(&frame("spot.order_place", "201", req_id, false, json!({ "id": "1", "currency_pair": "BTC_USDT" }))).unwrap();
+    assert_eq!(account.pending_count(), 0);
+
+    let stats = account.latency_stats();
+    assert_eq!(stats.latencies(1).len(), 1);
+    assert_eq!(stats.latencies(2).len(), 1);
+    assert!(stats.p95(2).unwrap() >= stats.p95(1).unwrap());
+    assert_eq!(stats.lost_count(), 0);
+}